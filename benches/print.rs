@@ -0,0 +1,102 @@
+//! Compares reading a large file for `pngme print` into owned chunks with
+//! borrowing them, in time and in allocations.
+//!
+//! Run with `cargo bench --bench print`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    io::{Write, sink},
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use criterion::{Criterion, Throughput};
+use pngme::{Chunk, ChunkType, Png, PngRef};
+
+// Counts every allocation, to show what borrowing saves
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const CHUNKS: usize = 1024;
+const CHUNK_SIZE: usize = 16 * 1024;
+
+// A 16 MiB file with the IDAT chunks a large image would have
+fn large_png() -> Vec<u8> {
+    let chunk = |chunk_type: &str, data: Vec<u8>| {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+    };
+    let ihdr = [0, 0, 16, 0, 0, 0, 16, 0, 8, 6, 0, 0, 0].to_vec();
+
+    let mut chunks = vec![chunk("IHDR", ihdr)];
+    for i in 0..CHUNKS {
+        chunks.push(chunk("IDAT", vec![i as u8; CHUNK_SIZE]));
+    }
+    chunks.push(chunk("IEND", Vec::new()));
+    Png::from_chunks(chunks).as_bytes()
+}
+
+// What print does once the file is read, with the output thrown away
+fn print_owned(file: &[u8]) {
+    let png = Png::parse_lenient(file).unwrap();
+    let mut out = sink();
+    for chunk in png.chunks() {
+        writeln!(out, "{}", chunk).unwrap();
+    }
+    black_box(png);
+}
+
+fn print_borrowed(file: &[u8]) {
+    let png = PngRef::parse_lenient(file).unwrap();
+    let mut out = sink();
+    for chunk in png.chunks() {
+        writeln!(out, "{}", chunk).unwrap();
+    }
+    black_box(png);
+}
+
+fn report_allocations(name: &str, file: &[u8], print: fn(&[u8])) {
+    let (count, bytes) = (
+        ALLOCATIONS.load(Ordering::Relaxed),
+        ALLOCATED.load(Ordering::Relaxed),
+    );
+    print(file);
+    println!(
+        "{}: {} allocations, {} bytes allocated for a {} byte file",
+        name,
+        ALLOCATIONS.load(Ordering::Relaxed) - count,
+        ALLOCATED.load(Ordering::Relaxed) - bytes,
+        file.len()
+    );
+}
+
+fn main() {
+    let file = large_png();
+    report_allocations("owned", &file, print_owned);
+    report_allocations("borrowed", &file, print_borrowed);
+
+    let mut criterion = Criterion::default().configure_from_args();
+    let mut group = criterion.benchmark_group("print");
+    group.throughput(Throughput::Bytes(file.len() as u64));
+    group.bench_function("owned", |b| b.iter(|| print_owned(black_box(&file))));
+    group.bench_function("borrowed", |b| b.iter(|| print_borrowed(black_box(&file))));
+    group.finish();
+    criterion.final_summary();
+}
@@ -0,0 +1,509 @@
+use std::fmt::Display;
+
+use serde::Serialize;
+
+use crate::{
+    chunk::ChunkRef, ihdr::ColorType, pixels::Image, png::PngRef, signature::SIGNATURE_CHUNK,
+    text::TextualChunk,
+};
+
+/// Text chunks holding more bytes than this are reported.
+pub const LARGE_TEXT_CHUNK: usize = 2048;
+
+/// Scores from this value up are reported as likely carrying hidden data.
+pub const SUSPICIOUS_SCORE: u32 = 50;
+
+/// One trait of a file that hints at hidden data, with how much it weighs.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Finding {
+    /// Points added to the score of the file, out of 100.
+    pub score: u32,
+    pub chunk_index: Option<usize>,
+    pub chunk_type: Option<String>,
+    pub message: String,
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[+{}] ", self.score)?;
+        if let (Some(index), Some(chunk_type)) = (self.chunk_index, &self.chunk_type) {
+            write!(f, "{} chunk #{}: ", chunk_type, index)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Result of the statistical tests on the least significant bits of the
+/// 8-bit colour samples of an image.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LsbStatistics {
+    /// Probability from the chi-square attack that the values of each pair
+    /// differing only in their low bit were equalised by embedding. Close to
+    /// 1 when most samples carry hidden bits.
+    pub chi_square_p: f64,
+    /// Fraction of samples whose low bit was replaced, estimated by RS
+    /// analysis. Sensitive to partial and scattered embedding.
+    pub rs_rate: f64,
+}
+
+/// Everything suspicious found in one file.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Analysis {
+    /// Sum of the scores of the findings, capped at 100.
+    pub score: u32,
+    pub findings: Vec<Finding>,
+    /// Bytes found after the IEND chunk.
+    pub trailing_bytes: usize,
+    /// Missing when the image has no 8-bit colour samples to test.
+    pub lsb: Option<LsbStatistics>,
+}
+
+impl Analysis {
+    pub fn is_suspicious(&self) -> bool {
+        self.score >= SUSPICIOUS_SCORE
+    }
+
+    /// One word summary of the score.
+    pub fn verdict(&self) -> &'static str {
+        match self.score {
+            score if score >= SUSPICIOUS_SCORE => "likely hidden data",
+            score if score >= 20 => "suspicious",
+            _ => "clean",
+        }
+    }
+
+    fn file(&mut self, score: u32, message: String) {
+        self.findings.push(Finding {
+            score,
+            chunk_index: None,
+            chunk_type: None,
+            message,
+        });
+    }
+
+    fn chunk(&mut self, score: u32, index: usize, chunk: &ChunkRef, message: String) {
+        self.findings.push(Finding {
+            score,
+            chunk_index: Some(index),
+            chunk_type: Some(chunk.chunk_type().to_string()),
+            message,
+        });
+    }
+}
+
+/// Looks for signs of hidden data in the bytes of a PNG file: private
+/// chunks, data after IEND, oversized text and statistical traces of LSB
+/// embedding in the pixels.
+pub fn analyze(bytes: &[u8]) -> crate::Result<Analysis> {
+    let end = iend_end(bytes).unwrap_or(bytes.len());
+    let png = PngRef::try_from(&bytes[..end])?;
+    let mut analysis = Analysis {
+        score: 0,
+        findings: Vec::new(),
+        trailing_bytes: bytes.len() - end,
+        lsb: None,
+    };
+
+    if analysis.trailing_bytes > 0 {
+        analysis.file(
+            50,
+            format!("{} byte(s) after the IEND chunk", analysis.trailing_bytes),
+        );
+    }
+
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        let chunk_type = chunk.chunk_type();
+        let length = chunk.data().len();
+        if !chunk_type.is_public() && chunk_type.is_critical() {
+            analysis.chunk(
+                40,
+                index,
+                chunk,
+                format!("private critical chunk of {} bytes", length),
+            );
+        } else if !chunk_type.is_public() {
+            // Signatures are a legitimate private chunk, but still worth a mention
+            let score = if chunk_type.to_string() == SIGNATURE_CHUNK {
+                5
+            } else {
+                30
+            };
+            analysis.chunk(
+                score,
+                index,
+                chunk,
+                format!("private ancillary chunk of {} bytes", length),
+            );
+        } else if TextualChunk::is_textual(chunk_type) && length > LARGE_TEXT_CHUNK {
+            analysis.chunk(20, index, chunk, format!("text chunk of {} bytes", length));
+        }
+    }
+
+    if let Ok(image) = png.decode_image() {
+        analysis.lsb = lsb_statistics(&image);
+    }
+    if let Some(lsb) = analysis.lsb {
+        if lsb.chi_square_p >= 0.5 {
+            analysis.file(
+                (lsb.chi_square_p * 40.0).round() as u32,
+                format!(
+                    "chi-square attack: sample pairs are equalised (p = {:.3})",
+                    lsb.chi_square_p
+                ),
+            );
+        }
+        if lsb.rs_rate >= 0.1 {
+            analysis.file(
+                ((lsb.rs_rate * 100.0).round() as u32).min(50),
+                format!(
+                    "RS analysis: about {:.0}% of the low bits look replaced",
+                    lsb.rs_rate * 100.0
+                ),
+            );
+        }
+    }
+
+    analysis.score = analysis
+        .findings
+        .iter()
+        .map(|finding| finding.score)
+        .sum::<u32>()
+        .min(100);
+    Ok(analysis)
+}
+
+// Offset just past the IEND chunk, if the chunk lengths lead to one
+fn iend_end(bytes: &[u8]) -> Option<usize> {
+    let mut offset = 8;
+    while offset + 8 <= bytes.len() {
+        let length = u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize;
+        let end = offset.checked_add(12)?.checked_add(length)?;
+        if &bytes[offset + 4..offset + 8] == b"IEND" {
+            return (end <= bytes.len()).then_some(end);
+        }
+        offset = end;
+    }
+    None
+}
+
+// Runs both tests on every 8-bit colour channel, alpha is left out as it is
+// usually flat
+fn lsb_statistics(image: &Image) -> Option<LsbStatistics> {
+    let colour_channels = match image.color_type() {
+        ColorType::Grayscale | ColorType::GrayscaleAlpha => 1,
+        ColorType::Rgb | ColorType::Rgba => 3,
+        ColorType::Indexed => return None,
+    };
+    if image.bit_depth() != 8 {
+        return None;
+    }
+
+    let planes: Vec<Vec<Vec<i32>>> = (0..colour_channels)
+        .map(|channel| {
+            (0..image.height())
+                .map(|y| {
+                    (0..image.width())
+                        .map(|x| image.sample(x, y, channel) as i32)
+                        .collect()
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut histogram = [0u64; 256];
+    for value in planes.iter().flatten().flatten() {
+        histogram[*value as usize] += 1;
+    }
+    let rates: Vec<f64> = planes.iter().filter_map(|plane| rs_rate(plane)).collect();
+
+    Some(LsbStatistics {
+        chi_square_p: chi_square_p(&histogram),
+        rs_rate: if rates.is_empty() {
+            0.0
+        } else {
+            rates.iter().sum::<f64>() / rates.len() as f64
+        },
+    })
+}
+
+// Westfeld and Pfitzmann's attack: embedding random bits makes the counts of
+// 2k and 2k + 1 converge, so a good fit to their mean is suspicious
+fn chi_square_p(histogram: &[u64; 256]) -> f64 {
+    let mut statistic = 0.0;
+    let mut categories = 0;
+    for pair in histogram.chunks(2) {
+        let expected = (pair[0] + pair[1]) as f64 / 2.0;
+        if expected < 1.0 {
+            continue;
+        }
+        statistic += (pair[0] as f64 - expected).powi(2) / expected;
+        categories += 1;
+    }
+    if categories < 2 {
+        return 0.0;
+    }
+    1.0 - lower_gamma((categories - 1) as f64 / 2.0, statistic / 2.0)
+}
+
+// Fridrich's RS analysis on groups of four horizontal neighbours, returning
+// the estimated fraction of samples with a replaced low bit
+fn rs_rate(plane: &[Vec<i32>]) -> Option<f64> {
+    let flipped: Vec<Vec<i32>> = plane
+        .iter()
+        .map(|row| row.iter().map(|v| v ^ 1).collect())
+        .collect();
+
+    let (r_m, s_m, r_n, s_n) = rs_counts(plane)?;
+    let (r_m1, s_m1, r_n1, s_n1) = rs_counts(&flipped)?;
+    let d0 = r_m - s_m;
+    let d1 = r_m1 - s_m1;
+    let dn0 = r_n - s_n;
+    let dn1 = r_n1 - s_n1;
+
+    let a = 2.0 * (d1 + d0);
+    let b = dn0 - dn1 - d1 - 3.0 * d0;
+    let c = d0 - dn0;
+    let x = if a.abs() < f64::EPSILON {
+        if b.abs() < f64::EPSILON {
+            return None;
+        }
+        -c / b
+    } else {
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let root = discriminant.sqrt();
+        let (x1, x2) = ((-b + root) / (2.0 * a), (-b - root) / (2.0 * a));
+        if x1.abs() < x2.abs() { x1 } else { x2 }
+    };
+    Some((x / (x - 0.5)).clamp(0.0, 1.0))
+}
+
+// Fractions of regular and singular groups under the mask [0, 1, 1, 0] and
+// its negation
+fn rs_counts(plane: &[Vec<i32>]) -> Option<(f64, f64, f64, f64)> {
+    let smoothness = |g: &[i32]| g.windows(2).map(|w| (w[1] - w[0]).abs()).sum::<i32>();
+    let flip = |v: i32| v ^ 1;
+    let shifted_flip = |v: i32| ((v + 1) ^ 1) - 1;
+
+    let (mut groups, mut r_m, mut s_m, mut r_n, mut s_n) = (0, 0, 0, 0, 0);
+    for row in plane {
+        for group in row.chunks_exact(4) {
+            let original = smoothness(group);
+            let masked = smoothness(&[group[0], flip(group[1]), flip(group[2]), group[3]]);
+            let negated = smoothness(&[
+                group[0],
+                shifted_flip(group[1]),
+                shifted_flip(group[2]),
+                group[3],
+            ]);
+            groups += 1;
+            r_m += (masked > original) as u32;
+            s_m += (masked < original) as u32;
+            r_n += (negated > original) as u32;
+            s_n += (negated < original) as u32;
+        }
+    }
+    if groups == 0 {
+        return None;
+    }
+    let fraction = |count: u32| count as f64 / groups as f64;
+    Some((fraction(r_m), fraction(s_m), fraction(r_n), fraction(s_n)))
+}
+
+// Regularised lower incomplete gamma function P(a, x), which gives the
+// chi-square distribution function
+fn lower_gamma(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    let prefix = (-x + a * x.ln() - ln_gamma(a)).exp();
+    if x < a + 1.0 {
+        let (mut term, mut sum) = (1.0 / a, 1.0 / a);
+        for n in 1..1000 {
+            term *= x / (a + n as f64);
+            sum += term;
+            if term.abs() < sum.abs() * 1e-15 {
+                break;
+            }
+        }
+        (sum * prefix).min(1.0)
+    } else {
+        // Continued fraction for the upper function, evaluated with Lentz's method
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..1000 {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = b + an / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < 1e-15 {
+                break;
+            }
+        }
+        (1.0 - prefix * h).max(0.0)
+    }
+}
+
+// Lanczos approximation of ln Γ(x) for x > 0
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let mut series = 1.000000000190015;
+    for (i, coefficient) in COEFFICIENTS.iter().enumerate() {
+        series += coefficient / (x + 1.0 + i as f64);
+    }
+    -tmp + (2.5066282746310005 * series / x).ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::{
+        chunk::Chunk,
+        chunk_type::ChunkType,
+        encoder::EncoderSettings,
+        lsb::{self, LsbOptions},
+        pixels::Samples,
+        png::{Png, tests::PNG_FILE},
+    };
+
+    // A smooth photo-like RGB image with a little noise, whose values are
+    // all even as is common after editing
+    fn photo() -> Png {
+        let (width, height) = (128u32, 96u32);
+        let mut noise = 0x2545F491u32;
+        let mut samples = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                for c in 0..3 {
+                    noise ^= noise << 13;
+                    noise ^= noise >> 17;
+                    noise ^= noise << 5;
+                    let base = 128.0
+                        + 60.0 * (x as f64 / (9.0 + c as f64)).sin()
+                        + 40.0 * (y as f64 / 7.0).cos();
+                    let value = base as i32 + (noise % 5) as i32 - 2;
+                    samples.push((value.clamp(0, 255) & !1) as u8);
+                }
+            }
+        }
+        let image =
+            Image::new(width, height, ColorType::Rgb, 8, None, Samples::U8(samples)).unwrap();
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.retain_chunks(|c| c.chunk_type().to_string() != "RuSt");
+        png.encode_image(&image, &EncoderSettings::default())
+            .unwrap();
+        png
+    }
+
+    #[test]
+    fn test_clean_image() {
+        let analysis = analyze(&photo().as_bytes()).unwrap();
+
+        assert_eq!(analysis.verdict(), "clean", "{:?}", analysis);
+        let lsb = analysis.lsb.unwrap();
+        assert!(lsb.chi_square_p < 0.01);
+        assert!(lsb.rs_rate < 0.05);
+    }
+
+    #[test]
+    fn test_full_lsb_embedding() {
+        let mut png = photo();
+        let mut image = png.decode_image().unwrap();
+        let options = LsbOptions {
+            key: Some("key".to_string()),
+            ..Default::default()
+        };
+        let capacity = lsb::capacity(&image, &options).unwrap();
+        let payload: Vec<u8> = (0..capacity).map(|i| (i * 7919 % 251) as u8).collect();
+        lsb::embed(&mut image, &payload, &options).unwrap();
+        png.encode_image(&image, &EncoderSettings::default())
+            .unwrap();
+
+        let analysis = analyze(&png.as_bytes()).unwrap();
+        let lsb = analysis.lsb.unwrap();
+        assert!(lsb.chi_square_p > 0.9, "{:?}", lsb);
+        assert!(lsb.rs_rate > 0.5, "{:?}", lsb);
+        assert!(analysis.is_suspicious());
+    }
+
+    #[test]
+    fn test_partial_lsb_embedding() {
+        let mut png = photo();
+        let mut image = png.decode_image().unwrap();
+        let options = LsbOptions {
+            key: Some("key".to_string()),
+            ..Default::default()
+        };
+        let capacity = lsb::capacity(&image, &options).unwrap();
+        lsb::embed(&mut image, &vec![0x5A; capacity / 2], &options).unwrap();
+        png.encode_image(&image, &EncoderSettings::default())
+            .unwrap();
+
+        let rate = analyze(&png.as_bytes()).unwrap().lsb.unwrap().rs_rate;
+        assert!((0.3..0.7).contains(&rate), "{}", rate);
+    }
+
+    #[test]
+    fn test_suspicious_chunks() {
+        let mut png = photo();
+        png.insert_chunk(Chunk::new(
+            ChunkType::from_str("prIv").unwrap(),
+            vec![1; 10],
+        ))
+        .unwrap();
+        png.insert_chunk(
+            TextualChunk::Text(crate::text::Text {
+                keyword: "Comment".to_string(),
+                text: "a".repeat(LARGE_TEXT_CHUNK + 1),
+            })
+            .to_chunk()
+            .unwrap(),
+        )
+        .unwrap();
+        let mut bytes = png.as_bytes();
+        bytes.extend_from_slice(b"appended secret");
+
+        let analysis = analyze(&bytes).unwrap();
+        assert_eq!(analysis.trailing_bytes, 15);
+        let types: Vec<Option<&str>> = analysis
+            .findings
+            .iter()
+            .map(|f| f.chunk_type.as_deref())
+            .collect();
+        assert_eq!(types, vec![None, Some("prIv"), Some("tEXt")]);
+        assert_eq!(analysis.score, 100);
+    }
+
+    #[test]
+    fn test_chi_square_distribution() {
+        // Known values of the chi-square distribution function
+        assert!((lower_gamma(0.5, 3.841 / 2.0) - 0.95).abs() < 1e-3);
+        assert!((lower_gamma(5.0, 18.307 / 2.0) - 0.95).abs() < 1e-3);
+        assert!((lower_gamma(50.0, 124.342 / 2.0) - 0.95).abs() < 1e-3);
+    }
+}
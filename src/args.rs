@@ -1,4 +1,5 @@
-use clap::{Parser, Subcommand};
+use clap::{Args as ClapArgs, Parser, Subcommand, ValueEnum};
+use pngme::output::DEFAULT_BACKUP_SUFFIX;
 
 #[derive(Parser, Debug)]
 #[command(name = "pngme")]
@@ -14,13 +15,240 @@ pub enum Commands {
     /// Encodes a message into a chunk from a specified file
     Encode {
         filepath: String,
-        chunk: String,
-        message: String,
+        /// Chunk type to store the message in, not given with --method lsb
+        #[arg(
+            required_unless_present = "method",
+            required_if_eq("method", "chunk"),
+            conflicts_with = "LsbArgs"
+        )]
+        chunk: Option<String>,
+        #[command(flatten)]
+        message: MessageArgs,
+        /// Where the message is hidden
+        #[arg(long, value_enum, default_value_t)]
+        method: Method,
+        #[command(flatten)]
+        lsb: LsbArgs,
+        #[command(flatten)]
+        seal: SealArgs,
+        /// Spread the message over this many chunks
+        #[arg(long, value_name = "N", requires = "chunk")]
+        split: Option<usize>,
+        /// Name the message, so several can be stored in the same chunk type
+        #[arg(long, value_name = "NAME", requires = "chunk")]
+        label: Option<String>,
     },
     /// Decodes and prints a hidden message in the specified file and chunk
-    Decode { filepath: String, chunk: String },
-    /// Removs a message from a file, if it exists
-    Remove { filepath: String, chunk: String },
-    /// Prints all the chunks of a given file
+    Decode {
+        filepath: String,
+        /// Chunk type holding the message, not given with --method lsb
+        #[arg(
+            required_unless_present = "method",
+            required_if_eq("method", "chunk"),
+            conflicts_with = "LsbArgs"
+        )]
+        chunk: Option<String>,
+        /// Where the message is hidden
+        #[arg(long, value_enum, default_value_t)]
+        method: Method,
+        #[command(flatten)]
+        lsb: LsbArgs,
+        #[command(flatten)]
+        open: OpenArgs,
+        /// Decode the message with this label
+        #[arg(long, value_name = "NAME", requires = "chunk")]
+        label: Option<String>,
+    },
+    /// Removs a message from a file, if it exists, with all its chunks
+    Remove {
+        filepath: String,
+        chunk: String,
+        /// Remove the message with this label
+        #[arg(long, value_name = "NAME", conflicts_with = "all")]
+        label: Option<String>,
+        /// Remove every chunk of this type
+        #[arg(long)]
+        all: bool,
+        /// Keep the original file, named with this suffix (.bak by default)
+        #[arg(long, value_name = "SUFFIX", num_args = 0..=1, require_equals = true, default_missing_value = DEFAULT_BACKUP_SUFFIX, value_parser = backup_suffix)]
+        backup: Option<String>,
+    },
+    /// Lists the labeled messages in a file with their sizes and dates
+    List { filepath: String },
+    /// Prints all the chunks of a given file, even a damaged one
     Print { filepath: String },
+    /// Recomputes wrong CRCs and drops unreadable bytes of a damaged file
+    Repair { filepath: String },
+    /// Prints the dimensions and format of an image
+    Info { filepath: String },
+    /// Prints how many bytes can be hidden in the pixels of an image
+    Capacity {
+        filepath: String,
+        #[command(flatten)]
+        lsb: LsbArgs,
+    },
+    /// Checks a file against the structural rules of the PNG specification
+    Validate {
+        filepath: String,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Scores files on how likely they are to carry hidden data
+    Analyze {
+        #[arg(required = true)]
+        filepaths: Vec<String>,
+        /// Print the reports as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Reads and edits tEXt, zTXt and iTXt metadata
+    Text {
+        #[command(subcommand)]
+        cmd: TextCommands,
+    },
+    /// Generates a key pair for receiving encrypted messages or signing files
+    Keygen {
+        /// File to write the secret key to, printed if omitted
+        #[arg(short, long)]
+        output: Option<String>,
+        /// Generate an Ed25519 signing key instead of an encryption identity
+        #[arg(long)]
+        signing: bool,
+    },
+    /// Signs all the chunks of a file with an Ed25519 key
+    Sign {
+        filepath: String,
+        /// File holding the signing key
+        #[arg(long)]
+        key: String,
+    },
+    /// Verifies a file's signature and reports the chunks changed since signing
+    Verify {
+        filepath: String,
+        /// Only accept signatures made with this public key
+        #[arg(long)]
+        public_key: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum TextCommands {
+    /// Lists all textual metadata
+    List { filepath: String },
+    /// Prints the text stored under a keyword
+    Get { filepath: String, keyword: String },
+    /// Stores text under a keyword, replacing any previous value
+    Set {
+        filepath: String,
+        keyword: String,
+        value: String,
+        /// Compress the text (zTXt, or compressed iTXt)
+        #[arg(long)]
+        compress: bool,
+        /// Language tag of the text, stored in an iTXt chunk
+        #[arg(long)]
+        language: Option<String>,
+        /// Keyword translated into the language of the text
+        #[arg(long)]
+        translated_keyword: Option<String>,
+    },
+    /// Deletes the text stored under a keyword
+    Delete { filepath: String, keyword: String },
+}
+
+#[derive(ClapArgs, Debug, Clone)]
+pub struct MessageArgs {
+    /// Message to hide
+    #[arg(required_unless_present_any = ["text", "file", "stdin"], conflicts_with_all = ["text", "file", "stdin"])]
+    pub message: Option<String>,
+    /// Message to hide, given this way with --method lsb as there is no chunk before it
+    #[arg(short = 'm', long = "message", value_name = "MESSAGE", conflicts_with_all = ["file", "stdin"])]
+    pub text: Option<String>,
+    /// Hide the contents of this file instead of a message
+    #[arg(long, conflicts_with = "stdin")]
+    pub file: Option<String>,
+    /// Hide everything read from stdin instead of a message
+    #[arg(long)]
+    pub stdin: bool,
+    /// MIME type stored with --file or --stdin, guessed from the contents if omitted
+    #[arg(long)]
+    pub mime_type: Option<String>,
+}
+
+#[derive(ClapArgs, Debug, Clone)]
+pub struct SealArgs {
+    #[command(flatten)]
+    pub passphrase: PassphraseArgs,
+    /// Public key allowed to decrypt the message, may be repeated
+    #[arg(long = "recipient", value_name = "PUBKEY", conflicts_with_all = ["passphrase", "passphrase_file"])]
+    pub recipients: Vec<String>,
+    /// Compress the message first, with deflate unless another method is given
+    #[arg(long, value_enum, value_name = "METHOD", num_args = 0..=1, default_missing_value = "deflate")]
+    pub compress: Option<CompressionMethod>,
+    /// Add error correction for up to this many corrupted bytes in every 255
+    #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u8).range(1..=127))]
+    pub ecc: Option<u8>,
+    /// Keep the original file, named with this suffix (.bak by default)
+    #[arg(long, value_name = "SUFFIX", num_args = 0..=1, require_equals = true, default_missing_value = DEFAULT_BACKUP_SUFFIX, value_parser = backup_suffix)]
+    pub backup: Option<String>,
+}
+
+#[derive(ClapArgs, Debug, Clone)]
+pub struct OpenArgs {
+    #[command(flatten)]
+    pub passphrase: PassphraseArgs,
+    /// Identity file holding the secret key to decrypt the message
+    #[arg(long)]
+    pub identity: Option<String>,
+    /// Write the message to this file as is, or to stdout if it is -
+    #[arg(short, long)]
+    pub output: Option<String>,
+}
+
+#[derive(ClapArgs, Debug, Clone)]
+#[group(multiple = false)]
+pub struct PassphraseArgs {
+    /// Passphrase used to encrypt or decrypt the message
+    #[arg(long)]
+    pub passphrase: Option<String>,
+    /// File whose first line is the passphrase
+    #[arg(long)]
+    pub passphrase_file: Option<String>,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Method {
+    /// In an ancillary chunk of its own
+    #[default]
+    Chunk,
+    /// In the least significant bits of the pixel samples
+    Lsb,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionMethod {
+    Deflate,
+    Zstd,
+}
+
+#[derive(ClapArgs, Debug, Clone)]
+pub struct LsbArgs {
+    /// Low bits of each sample used to hide the message
+    #[arg(long, default_value_t = 1)]
+    pub bits: u8,
+    /// Channels used to hide the message, any of r, g, b and a
+    #[arg(long, default_value = "rgb")]
+    pub channels: String,
+    /// Secret that scatters the hidden bits across the image
+    #[arg(long)]
+    pub key: Option<String>,
+}
+
+// A suffix that keeps the backup next to the file, under another name
+fn backup_suffix(suffix: &str) -> Result<String, String> {
+    if suffix.is_empty() || suffix.contains(std::path::is_separator) {
+        return Err("must be a non-empty file name suffix".to_string());
+    }
+    Ok(suffix.to_string())
 }
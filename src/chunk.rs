@@ -1,11 +1,8 @@
-use std::{
-    fmt::Display,
-    io::{BufReader, Read},
-};
+use std::fmt::Display;
 
 use crc::CRC_32_ISO_HDLC;
 
-use crate::chunk_type::ChunkType;
+use crate::{Error, chunk_type::ChunkType};
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct Chunk {
@@ -20,52 +17,52 @@ impl TryFrom<&[u8]> for Chunk {
 
     // Required method
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        // Ensuring the slice has enough bytes
-        if value.len() < 12 {
-            return Err("try_from failed. Data has length less than 12.".into());
-        }
+        Chunk::parse(value, true)
+    }
+}
 
-        // Getting length
-        let mut reader = BufReader::new(value);
-        let mut length_buffer: [u8; 4] = [0, 0, 0, 0];
-        reader.read_exact(&mut length_buffer)?;
-        let data_length = u32::from_be_bytes(length_buffer);
+impl Chunk {
+    // Reads a chunk, length included, optionally accepting a wrong CRC
+    pub(crate) fn parse(value: &[u8], verify_crc: bool) -> crate::Result<Chunk> {
+        ChunkRef::parse(value, verify_crc).map(|chunk| chunk.to_chunk())
+    }
+}
 
-        // Getting Chunk Type
-        let mut ct_buffer: [u8; 4] = [0, 0, 0, 0];
-        reader.read_exact(&mut ct_buffer)?;
-        let chunk_type = ChunkType::try_from(ct_buffer)?;
+impl Display for Chunk {
+    // Required method
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        ChunkRef::from(self).fmt(f)
+    }
+}
 
-        // Getting data
-        let mut data: Vec<u8> = vec![0; data_length as usize];
-        reader.read_exact(&mut data)?;
+/// A chunk borrowed from the bytes it was read from, such as a whole file in
+/// memory, so reading it copies nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkRef<'a> {
+    chunk_type: ChunkType,
+    data: &'a [u8],
+    crc: u32,
+}
 
-        // Reading CRC
-        let mut crc_bytes: [u8; 4] = [0; 4];
-        reader.read_exact(&mut crc_bytes)?;
-        let stored_crc = u32::from_be_bytes(crc_bytes);
+impl<'a> TryFrom<&'a [u8]> for ChunkRef<'a> {
+    type Error = crate::Error;
 
-        // Calculate CRC for validation
-        let crc = crc::Crc::<u32>::new(&CRC_32_ISO_HDLC);
-        let crc_data: Vec<u8> = ct_buffer.iter().chain(data.iter()).copied().collect();
-        let calculated_crc = crc.checksum(&crc_data);
+    fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
+        ChunkRef::parse(value, true)
+    }
+}
 
-        // Validate CRC
-        if calculated_crc != stored_crc {
-            return Err("try_from failed. CRC mismatch.".into());
+impl<'a> From<&'a Chunk> for ChunkRef<'a> {
+    fn from(chunk: &'a Chunk) -> ChunkRef<'a> {
+        ChunkRef {
+            chunk_type: chunk.chunk_type,
+            data: &chunk.data,
+            crc: chunk.crc,
         }
-
-        Ok(Chunk {
-            length: data_length,
-            chunk_type,
-            data,
-            crc: stored_crc,
-        })
     }
 }
 
-impl Display for Chunk {
-    // Required method
+impl Display for ChunkRef<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "Chunk {{",)?;
         writeln!(f, "  Length: {}", self.length())?;
@@ -77,27 +74,94 @@ impl Display for Chunk {
     }
 }
 
-#[allow(dead_code)]
-impl Chunk {
-    pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Chunk {
-        let length: u32 = data.len() as u32;
+impl<'a> ChunkRef<'a> {
+    // Reads the chunk at the start of `value`, length included, optionally
+    // accepting a wrong CRC. Anything after the chunk is ignored.
+    pub(crate) fn parse(value: &'a [u8], verify_crc: bool) -> crate::Result<ChunkRef<'a>> {
+        // Ensuring the slice has enough bytes
+        if value.len() < 12 {
+            return Err(Error::TruncatedChunk {
+                offset: 0,
+                needed: 12 - value.len(),
+            });
+        }
 
-        let result: Vec<u8> = chunk_type
-            .bytes()
-            .iter()
-            .cloned()
-            .chain(data.clone())
-            .collect();
+        let data_length = u32::from_be_bytes(value[..4].try_into().unwrap()) as usize;
+        let chunk_type = ChunkType::try_from(<[u8; 4]>::try_from(&value[4..8]).unwrap())?;
+
+        let total_length = 12 + data_length;
+        if value.len() < total_length {
+            return Err(Error::TruncatedChunk {
+                offset: 0,
+                needed: total_length - value.len(),
+            });
+        }
+        let chunk = ChunkRef {
+            chunk_type,
+            data: &value[8..8 + data_length],
+            crc: u32::from_be_bytes(value[8 + data_length..total_length].try_into().unwrap()),
+        };
+
+        if verify_crc && chunk.crc != chunk.computed_crc() {
+            return Err(Error::CrcMismatch {
+                chunk_type: chunk_type.to_string(),
+                stored: chunk.crc,
+                computed: chunk.computed_crc(),
+                offset: 0,
+            });
+        }
+        Ok(chunk)
+    }
+
+    pub fn length(&self) -> u32 {
+        self.data.len() as u32
+    }
+
+    pub fn chunk_type(&self) -> &ChunkType {
+        &self.chunk_type
+    }
+
+    /// The data, borrowed for as long as the bytes the chunk was read from.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+
+    /// The CRC matching the type and data, see [`Chunk::computed_crc`].
+    pub fn computed_crc(&self) -> u32 {
         let crc = crc::Crc::<u32>::new(&CRC_32_ISO_HDLC);
-        let calculated_crc = crc.checksum(&result);
+        let mut digest = crc.digest();
+        digest.update(&self.chunk_type.bytes());
+        digest.update(self.data);
+        digest.finalize()
+    }
 
+    /// Copies the chunk into an owned [`Chunk`], keeping its stored CRC.
+    pub fn to_chunk(&self) -> Chunk {
         Chunk {
-            length: length,
-            chunk_type: chunk_type,
-            data: data,
-            crc: calculated_crc,
+            length: self.length(),
+            chunk_type: self.chunk_type,
+            data: self.data.to_vec(),
+            crc: self.crc,
         }
     }
+}
+
+#[allow(dead_code)]
+impl Chunk {
+    pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Chunk {
+        let mut chunk = Chunk {
+            length: data.len() as u32,
+            chunk_type,
+            data,
+            crc: 0,
+        };
+        chunk.crc = chunk.computed_crc();
+        chunk
+    }
 
     pub fn length(&self) -> u32 {
         self.length
@@ -115,23 +179,23 @@ impl Chunk {
         self.crc
     }
 
+    /// The CRC matching the type and data, which differs from [`Chunk::crc`]
+    /// when the chunk was read from a damaged file.
+    pub fn computed_crc(&self) -> u32 {
+        ChunkRef::from(self).computed_crc()
+    }
+
     pub fn data_as_string(&self) -> crate::Result<String> {
         Ok(String::from_utf8(self.data.clone())?)
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
-        let length: [u8; 4] = self.length.to_be_bytes();
-        let chunk_type: [u8; 4] = self.chunk_type.bytes();
-        let data: Vec<u8> = self.data.clone();
-        let crc: [u8; 4] = self.crc.to_be_bytes();
-
-        let result: Vec<u8> = length
-            .into_iter()
-            .chain(chunk_type.into_iter())
-            .chain(data.into_iter())
-            .chain(crc.into_iter())
-            .collect();
-        result
+        let mut bytes = Vec::with_capacity(12 + self.data.len());
+        bytes.extend_from_slice(&self.length.to_be_bytes());
+        bytes.extend_from_slice(&self.chunk_type.bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes.extend_from_slice(&self.crc.to_be_bytes());
+        bytes
     }
 }
 
@@ -243,6 +307,10 @@ mod tests {
         let chunk = Chunk::try_from(chunk_data.as_ref());
 
         assert!(chunk.is_err());
+
+        let chunk = Chunk::parse(chunk_data.as_ref(), false).unwrap();
+        assert_eq!(chunk.crc(), 2882656333);
+        assert_eq!(chunk.computed_crc(), 2882656334);
     }
 
     #[test]
@@ -265,4 +333,23 @@ mod tests {
 
         let _chunk_string = format!("{}", chunk);
     }
+
+    #[test]
+    fn test_chunk_ref_borrows_data() {
+        let chunk = testing_chunk();
+        let mut bytes = chunk.as_bytes();
+        bytes.extend_from_slice(b"next chunk");
+
+        let borrowed = ChunkRef::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(borrowed.data().as_ptr(), bytes[8..].as_ptr());
+        assert_eq!(borrowed.length(), 42);
+        assert_eq!(borrowed.to_chunk(), chunk);
+        assert_eq!(ChunkRef::from(&chunk), borrowed);
+        assert_eq!(borrowed.to_string(), chunk.to_string());
+
+        bytes[20] ^= 1;
+        assert!(ChunkRef::try_from(bytes.as_slice()).is_err());
+        let damaged = ChunkRef::parse(&bytes, false).unwrap();
+        assert_ne!(damaged.crc(), damaged.computed_crc());
+    }
 }
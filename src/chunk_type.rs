@@ -1,9 +1,10 @@
 use std::{fmt::Display, str::FromStr};
 
-#[derive(Debug, PartialEq, Eq)]
+use crate::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct ChunkType {
-    // values: [u8; 4],
-    values: Vec<u8>,
+    values: [u8; 4],
 }
 
 impl FromStr for ChunkType {
@@ -11,21 +12,12 @@ impl FromStr for ChunkType {
 
     // Required method
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut values: Vec<u8> = Vec::new();
-
-        if s.len() != 4 {
-            return Err("Could not convert from str".into());
-        }
-
-        for c in s.chars() {
-            if c.is_alphabetic() {
-                values.push(c as u8);
-            } else {
-                return Err("Non alphabetic character".into());
-            }
-        }
+        let values: [u8; 4] = s
+            .as_bytes()
+            .try_into()
+            .map_err(|_| Error::InvalidChunkType(s.to_string()))?;
 
-        Ok(ChunkType { values })
+        ChunkType::try_from(values).map_err(|_| Error::InvalidChunkType(s.to_string()))
     }
 }
 
@@ -34,34 +26,37 @@ impl TryFrom<[u8; 4]> for ChunkType {
 
     // Required method
     fn try_from(value: [u8; 4]) -> Result<Self, Self::Error> {
-        // If we were using a slice on the ChunkType type
-        // Ok(ChunkType { values: value })
+        if !value.iter().all(|b| b.is_ascii_alphabetic()) {
+            return Err(Error::InvalidChunkType(
+                String::from_utf8_lossy(&value).into_owned(),
+            ));
+        }
 
-        let result = value.into_iter().collect();
-        Ok(ChunkType { values: result })
+        Ok(ChunkType { values: value })
     }
 }
 
 impl Display for ChunkType {
     // Required method
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let s = String::from_utf8(self.values.clone()).map_err(|_| std::fmt::Error)?;
-        write!(f, "{}", s)
+        f.write_str(self.as_str())
     }
 }
 
 #[allow(dead_code)]
 impl ChunkType {
-    pub fn values(&self) -> &Vec<u8> {
+    pub fn values(&self) -> &[u8] {
         &self.values
     }
 
     pub fn bytes(&self) -> [u8; 4] {
         self.values
-            .get(0..4)
-            .expect("Error getting bytes")
-            .try_into()
-            .unwrap()
+    }
+
+    /// The type as text, without allocating like `to_string` does.
+    pub fn as_str(&self) -> &str {
+        // Only ASCII letters get past FromStr and TryFrom
+        std::str::from_utf8(&self.values).expect("chunk types are ASCII")
     }
 
     pub fn is_valid(&self) -> bool {
@@ -74,35 +69,35 @@ impl ChunkType {
         if !self.is_reserved_bit_valid() {
             return false;
         }
-        return true;
+        true
     }
 
     pub fn is_critical(&self) -> bool {
         if self.values[0].is_ascii_uppercase() {
             return true;
         }
-        return false;
+        false
     }
 
     pub fn is_public(&self) -> bool {
         if self.values[1].is_ascii_uppercase() {
             return true;
         }
-        return false;
+        false
     }
 
     pub fn is_reserved_bit_valid(&self) -> bool {
         if self.values[2].is_ascii_uppercase() {
             return true;
         }
-        return false;
+        false
     }
 
     pub fn is_safe_to_copy(&self) -> bool {
         if self.values[3].is_ascii_lowercase() {
             return true;
         }
-        return false;
+        false
     }
 }
 
@@ -195,6 +190,7 @@ mod tests {
     pub fn test_chunk_type_string() {
         let chunk = ChunkType::from_str("RuSt").unwrap();
         assert_eq!(&chunk.to_string(), "RuSt");
+        assert_eq!(chunk.as_str(), "RuSt");
     }
 
     #[test]
@@ -1,77 +1,1157 @@
-use std::{fs, str::FromStr};
+use std::{
+    fs,
+    io::{self, BufReader, Write},
+    str::FromStr,
+};
 
-use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png};
+use crate::{
+    Error, analyze,
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    crypto::{self, KdfParams, PublicKey, Scheme, StaticSecret},
+    encoder::EncoderSettings,
+    fec,
+    fragment::{self, Fragment},
+    ihdr::Ihdr,
+    input::Input,
+    label::{self, Label},
+    lsb::{self, LsbOptions},
+    output,
+    payload::{self, Compression, Payload},
+    png::{Diagnostic, Png, PngRef},
+    reader::ChunkReader,
+    rewrite::{Inserter, rewrite},
+    signature::{self, SigningKey, VerifyingKey},
+    text::{self, CompressedText, InternationalText, Text, TextualChunk},
+};
+
+/// Options for [`encode`].
+#[derive(Debug, Default, Clone)]
+pub struct EncodeOptions {
+    /// Encrypt the message with a key derived from this passphrase.
+    pub passphrase: Option<String>,
+    /// Encrypt the message for the holders of these public keys.
+    pub recipients: Vec<PublicKey>,
+    /// Compress the message before encrypting and hiding it.
+    pub compression: Compression,
+    /// Spread the message over this many chunks of the same type.
+    pub split: Option<usize>,
+    /// Name the message, so several can share a chunk type.
+    pub label: Option<String>,
+    /// Add Reed-Solomon codes able to correct this many corrupted bytes in
+    /// every 255 of each chunk, or of the pixel data.
+    pub error_correction: Option<u8>,
+    /// Keep the original file under its name followed by this suffix.
+    pub backup: Option<String>,
+}
+
+/// Options for [`decode`].
+#[derive(Default, Clone)]
+pub struct DecodeOptions {
+    /// Passphrase used to open an encrypted message.
+    pub passphrase: Option<String>,
+    /// Secret key used to open a message encrypted for recipients.
+    pub identity: Option<StaticSecret>,
+    /// Write the message to this file, or to stdout as is if it is `-`,
+    /// instead of printing it as text.
+    pub output: Option<String>,
+    /// Decode the message with this label rather than the first one.
+    pub label: Option<String>,
+}
+
+/// Options for [`remove`].
+#[derive(Debug, Default, Clone)]
+pub struct RemoveOptions {
+    /// Remove every chunk of the message with this label.
+    pub label: Option<String>,
+    /// Remove every chunk of the type.
+    pub all: bool,
+    /// Keep the original file under its name followed by this suffix.
+    pub backup: Option<String>,
+}
 
 // Encodes a message into a file
-pub fn encode(filepath: &String, chunk: &String, message: &String) -> crate::Result<()> {
-    let file = fs::read(&filepath)?;
+pub fn encode(
+    filepath: &str,
+    chunk: &str,
+    message: &Payload,
+    options: &EncodeOptions,
+) -> crate::Result<()> {
+    let edit = encoder(chunk, message, options)?;
+    rewrite_file(filepath, options.backup.as_deref(), edit)
+}
+
+// The edit that adds the chunks of a message to a file, refusing a label
+// already in use
+fn encoder<'a>(
+    chunk: &'a str,
+    message: &Payload,
+    options: &'a EncodeOptions,
+) -> crate::Result<impl FnMut(Option<Chunk>) -> crate::Result<Vec<Chunk>> + 'a> {
+    let chunk_type = ChunkType::from_str(chunk)?;
+    let label = options.label.as_deref().map(Label::new).transpose()?;
+    // Every chunk gets its own error correction, label included, so each can
+    // be repaired before the fragments are put back together
+    let finish = |data: Vec<u8>| -> crate::Result<Vec<u8>> {
+        let data = match &label {
+            Some(label) => label::wrap(label, &data),
+            None => data,
+        };
+        protect(data, options)
+    };
+
+    let data = seal(message, options)?;
+    let chunks = match options.split {
+        Some(count) => fragment::split(&data, count)?
+            .into_iter()
+            .map(|fragment| {
+                let data = finish(fragment.as_bytes())?;
+                Ok(Chunk::new(ChunkType::from_str(chunk)?, data))
+            })
+            .collect::<crate::Result<Vec<Chunk>>>()?,
+        None => vec![Chunk::new(chunk_type, finish(data)?)],
+    };
+
+    let mut inserter = Inserter::new(chunks);
+    Ok(move |next: Option<Chunk>| {
+        if let (Some(name), Some(next)) = (&options.label, &next)
+            && next.chunk_type().to_string() == chunk
+            && has_label(next, name)
+        {
+            return Err(Error::DuplicateLabel(name.clone()));
+        }
+        inserter.edit(next)
+    })
+}
+
+// Streams a file through `edit`, replacing the original only once the whole
+// file was safely written
+fn rewrite_file<F>(filepath: &str, backup: Option<&str>, edit: F) -> crate::Result<()>
+where
+    F: FnMut(Option<Chunk>) -> crate::Result<Vec<Chunk>>,
+{
+    let input = BufReader::new(fs::File::open(filepath)?);
+    output::replace(filepath, backup, |writer| {
+        rewrite(input, writer, edit)?;
+        Ok(())
+    })
+}
+
+// Decodes a message from a file
+pub fn decode(filepath: &str, chunk: &str, options: &DecodeOptions) -> crate::Result<()> {
+    let file = map_file(filepath)?;
+
+    let png = read_damaged(&file)?;
+
+    let chunk_type = ChunkType::from_str(chunk)?;
+    let (data, corrected) = find_message(&png, &chunk_type, options.label.as_deref())?;
+    report_corrections(corrected);
+    reveal(open(&data, options)?, options)
+}
+
+// The sealed data of the first message of a type, or of the one labeled
+// `name`, put back together if it was split, with the bytes corrected
+fn find_message(
+    png: &PngRef<'_>,
+    chunk_type: &ChunkType,
+    name: Option<&str>,
+) -> crate::Result<(Vec<u8>, usize)> {
+    let messages = messages(png, chunk_type);
+
+    let wanted = match name {
+        Some(name) => {
+            find_labeled(&messages, name).ok_or_else(|| labeled_not_found(chunk_type, name))?
+        }
+        None => messages
+            .first()
+            .ok_or_else(|| Error::ChunkNotFound(chunk_type.to_string()))?,
+    };
+    if !fragment::is_fragment(&wanted.data) {
+        return Ok((wanted.data.clone(), wanted.corrected));
+    }
 
+    // Gather the other fragments of the same payload, wherever they are
+    let first = Fragment::try_from(wanted.data.as_slice())?;
+    let mut corrected = wanted.corrected;
+    let mut fragments = Vec::new();
+    for other in messages.iter().filter(|other| other.label == wanted.label) {
+        match Fragment::try_from(other.data.as_slice()) {
+            Ok(fragment) if fragment.digest == first.digest => {
+                // The first fragment was already counted
+                if fragment.index != first.index {
+                    corrected += other.corrected;
+                }
+                fragments.push(fragment);
+            }
+            _ => {}
+        }
+    }
+    Ok((fragment::reassemble(fragments)?, corrected))
+}
+
+// Reads whatever can be read from a file, warning about any damage. Chunks
+// with a wrong CRC are kept, as messages may correct their own errors.
+fn read_damaged(file: &[u8]) -> crate::Result<PngRef<'_>> {
+    let png = PngRef::parse_lenient(file)?;
+    for diagnostic in png.diagnostics() {
+        eprintln!("Warning: {}", diagnostic);
+    }
+    Ok(png)
+}
+
+// Adds error correction to the data of a chunk or of the pixels, if asked to
+fn protect(data: Vec<u8>, options: &EncodeOptions) -> crate::Result<Vec<u8>> {
+    match options.error_correction {
+        Some(strength) => fec::protect(&data, strength),
+        None => Ok(data),
+    }
+}
+
+// Repairs data protected with error correction and counts the bytes fixed
+fn recover(data: &[u8]) -> crate::Result<(Vec<u8>, usize)> {
+    if fec::is_protected(data) {
+        fec::recover(data)
+    } else {
+        Ok((data.to_vec(), 0))
+    }
+}
+
+// Goes to stderr, so messages written to stdout stay intact
+fn report_corrections(corrected: usize) {
+    if corrected > 0 {
+        eprintln!("Corrected {} corrupted byte(s)", corrected);
+    }
+}
+
+// A message read from a chunk, repaired if it was protected
+struct Message {
+    label: Option<Label>,
+    data: Vec<u8>,
+    corrected: usize,
+    protected: bool,
+}
+
+// Error correction covers the label, but older files have it inside the
+// label instead, so both are repaired
+fn read_message(data: &[u8]) -> crate::Result<Message> {
+    let (labeled, corrected) = recover(data)?;
+    let (label, inner) = label::unwrap(&labeled)?;
+    let (message, fixed) = recover(inner)?;
+    Ok(Message {
+        label,
+        data: message,
+        corrected: corrected + fixed,
+        protected: fec::is_protected(data) || fec::is_protected(inner),
+    })
+}
+
+// The messages held by the chunks of a type, with their labels. Chunks that
+// cannot be read are skipped, so one damaged chunk does not hide the others.
+fn messages(png: &PngRef<'_>, chunk_type: &ChunkType) -> Vec<Message> {
+    png.chunks()
+        .iter()
+        .filter(|c| c.chunk_type() == chunk_type)
+        .filter_map(|c| match read_message(c.data()) {
+            Ok(message) => Some(message),
+            Err(err) => {
+                eprintln!("Warning: skipping {} chunk: {}", chunk_type, err);
+                None
+            }
+        })
+        .collect()
+}
+
+fn find_labeled<'m>(messages: &'m [Message], name: &str) -> Option<&'m Message> {
+    messages
+        .iter()
+        .find(|message| message.label.as_ref().is_some_and(|l| l.name == name))
+}
+
+// Whether a chunk holds the message, or the fragment of it, labeled `name`
+fn has_label(chunk: &Chunk, name: &str) -> bool {
+    matches!(read_message(chunk.data()), Ok(Message { label: Some(label), .. }) if label.name == name)
+}
+
+// The label and digest of the split message a chunk holds a fragment of
+fn fragment_of(chunk: &Chunk) -> Option<(Option<String>, [u8; fragment::DIGEST_LEN])> {
+    let message = read_message(chunk.data()).ok()?;
+    let fragment = Fragment::try_from(message.data.as_slice()).ok()?;
+    Some((message.label.map(|label| label.name), fragment.digest))
+}
+
+fn labeled_not_found(chunk_type: &ChunkType, name: &str) -> Error {
+    Error::ChunkNotFound(format!("{} labeled {:?}", chunk_type, name))
+}
+
+// Hides a message in the low bits of the pixels of a file
+pub fn encode_lsb(
+    filepath: &str,
+    message: &Payload,
+    options: &EncodeOptions,
+    lsb_options: &LsbOptions,
+) -> crate::Result<()> {
+    let file = fs::read(filepath)?;
     let mut png = Png::try_from(file.as_slice())?;
+    let interlace = png.ihdr()?.is_interlaced();
 
-    let chunk_type = ChunkType::from_str(&chunk)?;
-    let chunk = Chunk::new(chunk_type, message.as_bytes().to_vec());
+    let mut image = png.decode_image()?;
+    let data = protect(seal(message, options)?, options)?;
+    lsb::embed(&mut image, &data, lsb_options)?;
+    let settings = EncoderSettings {
+        interlace,
+        ..Default::default()
+    };
+    png.encode_image(&image, &settings)?;
 
-    png.append_chunk(chunk);
+    output::write(filepath, options.backup.as_deref(), &png.as_bytes())?;
 
-    fs::write(filepath, png.as_bytes())?;
+    Ok(())
+}
+
+// Decodes a message hidden in the low bits of the pixels of a file
+pub fn decode_lsb(
+    filepath: &str,
+    options: &DecodeOptions,
+    lsb_options: &LsbOptions,
+) -> crate::Result<()> {
+    let file = map_file(filepath)?;
+    let png = read_damaged(&file)?;
+
+    let image = png.decode_image()?;
+    let (data, corrected) = recover(&lsb::extract(&image, lsb_options)?)?;
+    report_corrections(corrected);
+    reveal(open(&data, options)?, options)
+}
+
+// Prints how many bytes can be hidden in the pixels of a file
+pub fn capacity(filepath: &str, lsb_options: &LsbOptions) -> crate::Result<()> {
+    let file = map_file(filepath)?;
+    let png = PngRef::try_from(&*file)?;
+
+    let image = png.decode_image()?;
+    println!(
+        "Capacity: {} bytes ({} bit(s) per channel, channels {})",
+        lsb::capacity(&image, lsb_options)?,
+        lsb_options.bits_per_channel,
+        lsb_options.channels
+    );
 
     Ok(())
 }
 
-// Decodes a message from a file
-pub fn decode(filepath: &String, chunk: &String) -> crate::Result<()> {
-    let file = fs::read(&filepath)?;
+// Compresses and encrypts a message as requested. Compression comes first,
+// as ciphertext does not compress.
+fn seal(message: &Payload, options: &EncodeOptions) -> crate::Result<Vec<u8>> {
+    let packed = payload::pack(message, options.compression)?;
+    // On stderr like the other notes, so stdout only carries what was asked for
+    if options.compression != Compression::None {
+        eprintln!(
+            "Compressed {} bytes to {} bytes ({:.1}%)",
+            message.data.len(),
+            packed.len(),
+            packed.len() as f64 * 100.0 / message.data.len().max(1) as f64
+        );
+    }
 
-    let png = Png::try_from(file.as_slice())?;
+    if let Some(passphrase) = &options.passphrase {
+        crypto::seal_with_passphrase(&packed, passphrase, KdfParams::default())
+    } else if !options.recipients.is_empty() {
+        crypto::seal_for_recipients(&packed, &options.recipients)
+    } else {
+        Ok(packed)
+    }
+}
 
-    let chunk_type = ChunkType::from_str(&chunk)?;
+// Decrypts a sealed message with the key it needs, then decompresses it
+fn open(data: &[u8], options: &DecodeOptions) -> crate::Result<Payload> {
+    let packed = match crypto::scheme(data) {
+        Some(Scheme::Passphrase) => {
+            let passphrase = options
+                .passphrase
+                .as_ref()
+                .ok_or(Error::KeyRequired("a passphrase"))?;
+            crypto::open_with_passphrase(data, passphrase)?
+        }
+        Some(Scheme::Recipients) => {
+            let identity = options
+                .identity
+                .as_ref()
+                .ok_or(Error::KeyRequired("an identity"))?;
+            crypto::open_with_identity(data, identity)?
+        }
+        None => data.to_vec(),
+    };
+    payload::unpack(&packed)
+}
 
-    let wanted_chunk = png
-        .chunks()
-        .iter()
-        .find(|c| c.chunk_type().to_string() == chunk_type.to_string());
+// Saves or prints a decoded message. Files and binary data are only
+// described unless an output is given, as they are not meant for a terminal.
+fn reveal(message: Payload, options: &DecodeOptions) -> crate::Result<()> {
+    let description = match (&message.filename, &message.mime_type) {
+        (Some(name), Some(mime_type)) => {
+            format!("{} ({}, {} bytes)", name, mime_type, message.data.len())
+        }
+        (None, Some(mime_type)) => format!("{} bytes of {}", message.data.len(), mime_type),
+        (Some(name), None) => format!("{} ({} bytes)", name, message.data.len()),
+        (None, None) => format!("{} bytes", message.data.len()),
+    };
+
+    match options.output.as_deref() {
+        Some("-") => io::stdout().write_all(&message.data)?,
+        Some(path) => {
+            fs::write(path, &message.data)?;
+            println!("Saved {} to {}", description, path);
+        }
+        None if message.filename.is_some() => {
+            println!("Attachment: {}, use --output to save it", description);
+        }
+        None => match String::from_utf8(message.data) {
+            Ok(text) => println!("Message: {}", text),
+            Err(_) => println!("Attachment: {}, use --output to save it", description),
+        },
+    }
+
+    Ok(())
+}
+
+// Removes a message from a file: the first message of a type with all its
+// fragments, the chunks of a labeled message, or every chunk of the type
+pub fn remove(filepath: &str, chunk: &str, options: &RemoveOptions) -> crate::Result<()> {
+    let edit = remover(ChunkType::from_str(chunk)?, options);
+    rewrite_file(filepath, options.backup.as_deref(), edit)
+}
+
+// The edit that drops the chunks `remove` was asked to, failing at the end
+// of the file if there were none
+fn remover(
+    chunk_type: ChunkType,
+    options: &RemoveOptions,
+) -> impl FnMut(Option<Chunk>) -> crate::Result<Vec<Chunk>> + '_ {
+    let mut removed = 0;
+    // Set from the first chunk removed when it holds a fragment
+    let mut split = None;
+    move |next| {
+        let Some(next) = next else {
+            return match (removed, &options.label) {
+                (0, Some(name)) => Err(labeled_not_found(&chunk_type, name)),
+                (0, None) => Err(Error::ChunkNotFound(chunk_type.to_string())),
+                _ => Ok(Vec::new()),
+            };
+        };
+        let wanted = next.chunk_type().to_string() == chunk_type.to_string()
+            && match &options.label {
+                _ if options.all => true,
+                Some(name) => has_label(&next, name),
+                None if removed == 0 => {
+                    split = fragment_of(&next);
+                    true
+                }
+                None => split.is_some() && fragment_of(&next) == split,
+            };
+        if wanted {
+            removed += 1;
+            Ok(Vec::new())
+        } else {
+            Ok(vec![next])
+        }
+    }
+}
+
+// Lists the labeled messages of a file, whatever chunks they are in
+pub fn list(filepath: &str) -> crate::Result<()> {
+    let file = map_file(filepath)?;
+    let png = read_damaged(&file)?;
+
+    // Messages split into fragments are listed once, with their total size
+    struct Listed {
+        chunk_type: String,
+        label: Label,
+        size: usize,
+        chunks: usize,
+        scheme: Option<Scheme>,
+        protected: bool,
+    }
+    let mut listed: Vec<Listed> = Vec::new();
+    for chunk in png.chunks() {
+        let Ok(Message {
+            label: Some(label),
+            data,
+            protected,
+            ..
+        }) = read_message(chunk.data())
+        else {
+            continue;
+        };
+        let chunk_type = chunk.chunk_type().to_string();
+        let data = data.as_slice();
+        // Only the first fragment shows how the payload is sealed
+        let (size, scheme) = match Fragment::try_from(data) {
+            Ok(fragment) if fragment.index == 0 => {
+                (fragment.data.len(), crypto::scheme(&fragment.data))
+            }
+            Ok(fragment) => (fragment.data.len(), None),
+            Err(_) => (data.len(), crypto::scheme(data)),
+        };
+        match listed
+            .iter_mut()
+            .find(|m| m.chunk_type == chunk_type && m.label.name == label.name)
+        {
+            Some(message) => {
+                message.size += size;
+                message.chunks += 1;
+                message.scheme = message.scheme.or(scheme);
+            }
+            None => listed.push(Listed {
+                chunk_type,
+                label,
+                size,
+                chunks: 1,
+                scheme,
+                protected,
+            }),
+        }
+    }
 
-    if let Some(found_chunk) = wanted_chunk {
-        let message = String::from_utf8(found_chunk.data().to_vec())?;
-        println!("Message: {}", message);
+    if listed.is_empty() {
+        println!("No labeled messages");
+    }
+    for message in listed {
+        let mut notes = vec![label::format_timestamp(message.label.created)];
+        match message.scheme {
+            Some(Scheme::Passphrase) => notes.push("encrypted with a passphrase".to_string()),
+            Some(Scheme::Recipients) => notes.push("encrypted for recipients".to_string()),
+            None => {}
+        }
+        if message.chunks > 1 {
+            notes.push(format!("split into {} chunks", message.chunks));
+        }
+        if message.protected {
+            notes.push("error corrected".to_string());
+        }
+        println!(
+            "{} {:?}: {} bytes, {}",
+            message.chunk_type,
+            message.label.name,
+            message.size,
+            notes.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+// Prints a message, if it exists
+pub fn print(filepath: &str) -> crate::Result<()> {
+    let file = map_file(filepath)?;
+    // Only borrows the chunks, so large files are never copied
+    let png = PngRef::parse_lenient(&file)?;
+
+    match png.ihdr() {
+        Ok(ihdr) => println!("Image: {}", ihdr),
+        Err(err) => println!("Image: unknown ({})", err),
+    }
+    let diagnostics = png.diagnostics();
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        println!("{}", chunk);
+        for diagnostic in diagnostics.iter().filter(|d| d.chunk() == Some(index)) {
+            println!("Damaged: {}", diagnostic);
+        }
+    }
+    for diagnostic in diagnostics.iter().filter(|d| d.chunk().is_none()) {
+        println!("Damaged: {}", diagnostic);
+    }
+
+    Ok(())
+}
+
+// Rewrites a damaged file with what could be read from it: CRCs are
+// recomputed, unreadable bytes dropped and a missing IEND added back
+pub fn repair(filepath: &str) -> crate::Result<()> {
+    let file = fs::read(filepath)?;
+    let png = Png::parse_lenient(&file)?;
+
+    let mut chunks = Vec::with_capacity(png.chunks().len() + 1);
+    for chunk in png.chunks() {
+        let chunk_type = ChunkType::from_str(&chunk.chunk_type().to_string())?;
+        chunks.push(Chunk::new(chunk_type, chunk.data().to_vec()));
+    }
+    let missing_iend = chunks
+        .last()
+        .is_none_or(|c| c.chunk_type().to_string() != "IEND");
+    if png.diagnostics().is_empty() && !missing_iend {
+        println!("Nothing to repair");
+        return Ok(());
+    }
+
+    for diagnostic in png.diagnostics() {
+        let fix = match diagnostic {
+            Diagnostic::CrcMismatch { .. } => "Recomputed CRC",
+            Diagnostic::TruncatedChunk { .. } => "Dropped chunk",
+            Diagnostic::Garbage { .. } | Diagnostic::TrailingData { .. } => "Removed bytes",
+        };
+        println!("{} ({})", fix, diagnostic);
+    }
+    if missing_iend {
+        chunks.push(Chunk::new(ChunkType::from_str("IEND")?, Vec::new()));
+        println!("Added missing IEND chunk");
+    }
+
+    output::write(filepath, None, &Png::from_chunks(chunks).as_bytes())?;
+
+    Ok(())
+}
+
+// Reports every spec violation in a file, as text or JSON
+pub fn validate(filepath: &str, json: bool) -> crate::Result<()> {
+    let file = map_file(filepath)?;
+    // Damage is reported along with the rest rather than stopping the check
+    let png = PngRef::parse_lenient(&file)?;
+
+    let report = png.validate();
+    if json {
+        println!("{}", report.to_json());
     } else {
-        return Err(format!("No message found in this file.").into());
+        for issue in &report.issues {
+            println!("{}", issue);
+        }
+        println!(
+            "{}: {} error(s), {} warning(s)",
+            filepath,
+            report.errors(),
+            report.warnings()
+        );
+    }
+
+    if !report.is_valid() {
+        return Err(Error::ValidationFailed {
+            errors: report.errors(),
+        });
+    }
+    Ok(())
+}
+
+// Scores each file on how likely it is to carry hidden data. Files that
+// cannot be read are reported and skipped.
+pub fn analyze(filepaths: &[String], json: bool) -> crate::Result<()> {
+    let mut reports = Vec::new();
+    let mut suspicious = 0;
+    let mut failure = None;
+    for filepath in filepaths {
+        let analysis = match map_file(filepath).and_then(|file| analyze::analyze(&file)) {
+            Ok(analysis) => analysis,
+            Err(err) => {
+                eprintln!("{}: {}", filepath, err);
+                failure.get_or_insert(err);
+                continue;
+            }
+        };
+        suspicious += analysis.is_suspicious() as usize;
+
+        if json {
+            reports.push(serde_json::json!({
+                "file": filepath,
+                "verdict": analysis.verdict(),
+                "analysis": analysis,
+            }));
+            continue;
+        }
+        println!(
+            "{}: score {}/100 ({})",
+            filepath,
+            analysis.score,
+            analysis.verdict()
+        );
+        for finding in &analysis.findings {
+            println!("  {}", finding);
+        }
+        if let Some(lsb) = analysis.lsb {
+            println!(
+                "  LSB statistics: chi-square p = {:.3}, RS embedding rate = {:.1}%",
+                lsb.chi_square_p,
+                lsb.rs_rate * 100.0
+            );
+        }
+    }
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&reports).expect("reports are always serializable")
+        );
     }
 
+    if let Some(err) = failure {
+        return Err(err);
+    }
+    if suspicious > 0 {
+        return Err(Error::HiddenDataSuspected { files: suspicious });
+    }
     Ok(())
 }
 
-// Removes a message from a file
-pub fn remove(filepath: &String, chunk: &String) -> crate::Result<()> {
-    let file = fs::read(&filepath)?;
+// Prints the dimensions and format of an image
+pub fn info(filepath: &str) -> crate::Result<()> {
+    // Streamed, as only the header and a chunk count are needed
+    let mut reader = ChunkReader::new(BufReader::new(fs::File::open(filepath)?))?;
+    let (mut ihdr, mut chunks) = (None, 0);
+    for chunk in reader.by_ref() {
+        let (_, chunk) = chunk?;
+        if ihdr.is_none() && chunk.chunk_type().to_string() == Ihdr::CHUNK_TYPE {
+            ihdr = Some(Ihdr::try_from(&chunk)?);
+        }
+        chunks += 1;
+    }
+    let ihdr = ihdr.ok_or_else(|| Error::ChunkNotFound(Ihdr::CHUNK_TYPE.to_string()))?;
+
+    println!("File: {}", filepath);
+    println!("Dimensions: {} x {} pixels", ihdr.width, ihdr.height);
+    println!(
+        "Color type: {} ({} channel(s))",
+        ihdr.color_type,
+        ihdr.color_type.channels()
+    );
+    println!("Bit depth: {} bits per sample", ihdr.bit_depth);
+    println!(
+        "Interlace: {}",
+        if ihdr.is_interlaced() {
+            "Adam7"
+        } else {
+            "none"
+        }
+    );
+    println!("Chunks: {}", chunks);
+    println!("Size: {} bytes", reader.offset());
+
+    Ok(())
+}
+
+// Generates a new identity or signing key, writing it to a file or printing it
+pub fn keygen(output: Option<&str>, signing: bool) -> crate::Result<()> {
+    let (secret, public_key) = if signing {
+        let key = signature::generate_signing_key();
+        (
+            signature::format_signing_key(&key),
+            signature::format_verifying_key(&key.verifying_key()),
+        )
+    } else {
+        let identity = crypto::generate_identity();
+        (
+            crypto::format_identity(&identity),
+            crypto::format_public_key(&PublicKey::from(&identity)),
+        )
+    };
+
+    match output {
+        Some(path) => {
+            let mut file = fs::OpenOptions::new();
+            file.write(true).create_new(true);
+            // The file holds a secret key, keep it private to its owner
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut file, 0o600);
+            file.open(path)?.write_all(secret.as_bytes())?;
+            println!("Public key: {}", public_key);
+        }
+        None => print!("{}", secret),
+    }
+
+    Ok(())
+}
+
+// Signs all the chunks of a file
+pub fn sign(filepath: &str, key: &SigningKey) -> crate::Result<()> {
+    let file = fs::read(filepath)?;
     let mut png = Png::try_from(file.as_slice())?;
 
-    let chunk_type = ChunkType::from_str(&chunk)?;
+    signature::sign(&mut png, key)?;
+
+    output::write(filepath, None, &png.as_bytes())?;
+
+    Ok(())
+}
+
+// Verifies the signature of a file, optionally requiring a specific signer
+pub fn verify(filepath: &str, expected_signer: Option<&VerifyingKey>) -> crate::Result<()> {
+    let file = map_file(filepath)?;
+    let png = PngRef::try_from(&*file)?;
+
+    let verification = signature::verify(png)?;
+    println!(
+        "Signed by: {}",
+        signature::format_verifying_key(&verification.signer)
+    );
+    if expected_signer.is_some_and(|key| *key != verification.signer) {
+        return Err(Error::InvalidSignature);
+    }
+
+    if verification.changes.is_empty() {
+        println!("Signature OK, no chunks changed.");
+        return Ok(());
+    }
+    for change in &verification.changes {
+        println!("  {}", change);
+    }
+    Err(Error::ContentModified {
+        changes: verification.changes.len(),
+    })
+}
+
+/// Options for [`text_set`].
+#[derive(Debug, Default, Clone)]
+pub struct TextOptions {
+    /// Deflate the text (zTXt, or compressed iTXt).
+    pub compress: bool,
+    /// Language of the text, forces an iTXt chunk.
+    pub language_tag: Option<String>,
+    /// Keyword translated into the language of the text, forces an iTXt chunk.
+    pub translated_keyword: Option<String>,
+}
 
-    let wanted_chunk = png
-        .chunks()
+// Maps a file the commands only read. They work on files given to the CLI,
+// which are expected to stay untouched until it exits, and the commands that
+// edit files rename a new file over the old one rather than writing in place,
+// which leaves an existing mapping as it was.
+fn map_file(filepath: &str) -> crate::Result<Input> {
+    // Safety: see above
+    unsafe { Input::open(filepath) }
+}
+
+// Parses every textual chunk of a file, skipping malformed ones with a
+// warning so they do not hide the others
+fn textual_chunks(png: &PngRef) -> Vec<TextualChunk> {
+    png.chunks()
         .iter()
-        .any(|c| c.chunk_type().to_string() == chunk_type.to_string());
+        .filter(|c| TextualChunk::is_textual(c.chunk_type()))
+        .filter_map(|c| match TextualChunk::try_from(*c) {
+            Ok(text) => Some(text),
+            Err(err) => {
+                eprintln!("Warning: skipping {} chunk: {}", c.chunk_type(), err);
+                None
+            }
+        })
+        .collect()
+}
 
-    if !wanted_chunk {
-        return Err(format!("No chunk of type {} was found in the file.", chunk).into());
+// Lists the textual metadata of a file
+pub fn text_list(filepath: &str) -> crate::Result<()> {
+    let file = map_file(filepath)?;
+    let png = PngRef::try_from(&*file)?;
+
+    for text in textual_chunks(&png) {
+        println!("{}", text);
     }
 
-    png.remove_first_chunk(&chunk)?;
+    Ok(())
+}
+
+// Prints the text stored under a keyword
+pub fn text_get(filepath: &str, keyword: &str) -> crate::Result<()> {
+    let file = map_file(filepath)?;
+    let png = PngRef::try_from(&*file)?;
 
-    fs::write(filepath, png.as_bytes())?;
+    let found = textual_chunks(&png)
+        .into_iter()
+        .find(|t| t.keyword() == keyword)
+        .ok_or_else(|| Error::ChunkNotFound(format!("text with keyword {:?}", keyword)))?;
+    println!("{}", found.text());
 
     Ok(())
 }
 
-// Prints a message, if it exists
-pub fn print(filepath: &String) -> crate::Result<()> {
-    let file = fs::read(&filepath)?;
-    let png = Png::try_from(file.as_slice())?;
+// Stores text under a keyword, replacing any previous text with that keyword.
+// tEXt is used when possible, iTXt when the text is not Latin-1 or a language
+// is given.
+pub fn text_set(
+    filepath: &str,
+    keyword: &str,
+    value: &str,
+    options: &TextOptions,
+) -> crate::Result<()> {
+    let file = fs::read(filepath)?;
+    let mut png = Png::try_from(file.as_slice())?;
 
-    for chunk in png.chunks() {
-        println!("{}", chunk);
+    let keyword = keyword.to_string();
+    let international = !text::is_latin1(value)
+        || options.language_tag.is_some()
+        || options.translated_keyword.is_some();
+    let text = if international {
+        TextualChunk::International(InternationalText {
+            keyword: keyword.clone(),
+            compressed: options.compress,
+            language_tag: options.language_tag.clone().unwrap_or_default(),
+            translated_keyword: options.translated_keyword.clone().unwrap_or_default(),
+            text: value.to_string(),
+        })
+    } else if options.compress {
+        TextualChunk::Compressed(CompressedText {
+            keyword: keyword.clone(),
+            text: value.to_string(),
+        })
+    } else {
+        TextualChunk::Text(Text {
+            keyword: keyword.clone(),
+            text: value.to_string(),
+        })
+    };
+    text::set_text(&mut png, &text)?;
+
+    output::write(filepath, None, &png.as_bytes())?;
+
+    Ok(())
+}
+
+// Removes all text stored under a keyword
+pub fn text_delete(filepath: &str, keyword: &str) -> crate::Result<()> {
+    let file = fs::read(filepath)?;
+    let mut png = Png::try_from(file.as_slice())?;
+
+    if text::remove_text(&mut png, keyword) == 0 {
+        return Err(Error::ChunkNotFound(format!(
+            "text with keyword {:?}",
+            keyword
+        )));
     }
 
+    output::write(filepath, None, &png.as_bytes())?;
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::PNG_FILE;
+
+    fn chunk_type() -> ChunkType {
+        ChunkType::from_str("ruSt").unwrap()
+    }
+
+    fn labeled(name: &str) -> EncodeOptions {
+        EncodeOptions {
+            label: Some(name.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn encoded(png: &[u8], message: &str, options: &EncodeOptions) -> crate::Result<Vec<u8>> {
+        let edit = encoder("ruSt", &Payload::new(message.as_bytes().to_vec()), options)?;
+        rewrite(png, Vec::new(), edit)
+    }
+
+    fn removed(png: &[u8], options: &RemoveOptions) -> crate::Result<Vec<u8>> {
+        rewrite(png, Vec::new(), remover(chunk_type(), options))
+    }
+
+    fn decoded(png: &[u8], name: Option<&str>) -> crate::Result<String> {
+        let png = PngRef::parse_lenient(png)?;
+        let (data, _) = find_message(&png, &chunk_type(), name)?;
+        let message = open(&data, &DecodeOptions::default())?;
+        Ok(String::from_utf8(message.data).unwrap())
+    }
+
+    fn count(png: &[u8]) -> usize {
+        let png = PngRef::try_from(png).unwrap();
+        png.chunks()
+            .iter()
+            .filter(|c| c.chunk_type() == &chunk_type())
+            .count()
+    }
+
+    #[test]
+    fn test_encode_rejects_duplicate_label() {
+        let png = encoded(&PNG_FILE, "first", &labeled("a")).unwrap();
+
+        let result = encoded(&png, "second", &labeled("a"));
+        assert!(matches!(result, Err(Error::DuplicateLabel(name)) if name == "a"));
+
+        let png = encoded(&png, "second", &labeled("b")).unwrap();
+        assert_eq!(decoded(&png, Some("a")).unwrap(), "first");
+        assert_eq!(decoded(&png, Some("b")).unwrap(), "second");
+    }
+
+    #[test]
+    fn test_remove_label() {
+        let png = encoded(&PNG_FILE, "plain", &EncodeOptions::default()).unwrap();
+        let png = encoded(&png, "first", &labeled("a")).unwrap();
+        let png = encoded(&png, "second", &labeled("b")).unwrap();
+
+        let options = RemoveOptions {
+            label: Some("a".to_string()),
+            ..Default::default()
+        };
+        let png = removed(&png, &options).unwrap();
+        assert_eq!(count(&png), 2);
+        assert!(matches!(
+            decoded(&png, Some("a")),
+            Err(Error::ChunkNotFound(_))
+        ));
+        assert_eq!(decoded(&png, Some("b")).unwrap(), "second");
+        assert_eq!(decoded(&png, None).unwrap(), "plain");
+
+        assert!(matches!(
+            removed(&png, &options),
+            Err(Error::ChunkNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_remove_all() {
+        let png = encoded(&PNG_FILE, "plain", &EncodeOptions::default()).unwrap();
+        let png = encoded(&png, "first", &labeled("a")).unwrap();
+
+        let options = RemoveOptions {
+            all: true,
+            ..Default::default()
+        };
+        let png = removed(&png, &options).unwrap();
+        assert_eq!(count(&png), 0);
+        assert_eq!(png.len(), PNG_FILE.len());
+        assert!(matches!(
+            removed(&png, &options),
+            Err(Error::ChunkNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_remove_every_fragment_of_split_message() {
+        let options = EncodeOptions {
+            split: Some(3),
+            ..Default::default()
+        };
+        let png = encoded(&PNG_FILE, "spread over three chunks", &options).unwrap();
+        let png = encoded(&png, "after", &EncodeOptions::default()).unwrap();
+        assert_eq!(count(&png), 4);
+
+        let png = removed(&png, &RemoveOptions::default()).unwrap();
+        assert_eq!(count(&png), 1);
+        assert_eq!(decoded(&png, None).unwrap(), "after");
+    }
+
+    #[test]
+    fn test_skips_unreadable_labels() {
+        // A label magic with nothing after it
+        let broken = Chunk::new(chunk_type(), label::MAGIC.to_vec());
+        let mut inserter = Inserter::new(vec![broken]);
+        let png = rewrite(PNG_FILE.as_ref(), Vec::new(), |next| inserter.edit(next)).unwrap();
+        let png = encoded(&png, "first", &labeled("a")).unwrap();
+        assert_eq!(count(&png), 2);
+
+        assert_eq!(decoded(&png, Some("a")).unwrap(), "first");
+        assert_eq!(decoded(&png, None).unwrap(), "first");
+
+        let options = RemoveOptions {
+            label: Some("a".to_string()),
+            ..Default::default()
+        };
+        let png = removed(&png, &options).unwrap();
+        assert_eq!(count(&png), 1);
+        assert!(matches!(decoded(&png, None), Err(Error::ChunkNotFound(_))));
+    }
+
+    fn split(count: usize, label: Option<&str>) -> EncodeOptions {
+        EncodeOptions {
+            split: Some(count),
+            label: label.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_decode_collects_fragments_across_chunks() {
+        let png = encoded(&PNG_FILE, "spread over three chunks", &split(3, None)).unwrap();
+
+        // Moves the first fragment to the end of the file
+        let mut held = None;
+        let png = rewrite(png.as_slice(), Vec::new(), |next| match next {
+            Some(next) if next.chunk_type() == &chunk_type() && held.is_none() => {
+                held = Some(next);
+                Ok(Vec::new())
+            }
+            Some(next) if next.chunk_type().to_string() == "IEND" => {
+                Ok(held.take().into_iter().chain([next]).collect())
+            }
+            next => Ok(next.into_iter().collect()),
+        })
+        .unwrap();
+        let png_ref = PngRef::try_from(png.as_slice()).unwrap();
+        let order: Vec<u32> = png_ref
+            .chunks()
+            .iter()
+            .filter(|c| c.chunk_type() == &chunk_type())
+            .map(|c| Fragment::try_from(c.data()).unwrap().index)
+            .collect();
+        assert_eq!(order, [1, 2, 0]);
+
+        assert_eq!(decoded(&png, None).unwrap(), "spread over three chunks");
+    }
+
+    #[test]
+    fn test_decode_mixes_labeled_and_split_messages() {
+        let png = encoded(&PNG_FILE, "labeled and split", &split(2, Some("a"))).unwrap();
+        let png = encoded(&png, "only split", &split(3, None)).unwrap();
+        let png = encoded(&png, "only labeled", &labeled("b")).unwrap();
+        assert_eq!(count(&png), 6);
+
+        assert_eq!(decoded(&png, Some("a")).unwrap(), "labeled and split");
+        assert_eq!(decoded(&png, Some("b")).unwrap(), "only labeled");
+        // The first chunk is a fragment of "a", which is reassembled as well
+        assert_eq!(decoded(&png, None).unwrap(), "labeled and split");
+
+        let options = RemoveOptions {
+            label: Some("a".to_string()),
+            ..Default::default()
+        };
+        let png = removed(&png, &options).unwrap();
+        assert_eq!(decoded(&png, None).unwrap(), "only split");
+    }
+
+    #[test]
+    fn test_decode_missing_fragment() {
+        let png = encoded(&PNG_FILE, "spread over three chunks", &split(3, None)).unwrap();
+
+        let mut seen = 0;
+        let png = rewrite(png.as_slice(), Vec::new(), |next| match next {
+            Some(next) if next.chunk_type() == &chunk_type() => {
+                seen += 1;
+                Ok(if seen == 2 { Vec::new() } else { vec![next] })
+            }
+            next => Ok(next.into_iter().collect()),
+        })
+        .unwrap();
+        assert_eq!(count(&png), 2);
+
+        assert!(matches!(
+            decoded(&png, None),
+            Err(Error::InvalidFragments(_))
+        ));
+    }
+
+    #[test]
+    fn test_decode_corrects_labeled_split_chunks() {
+        let options = EncodeOptions {
+            error_correction: Some(8),
+            ..split(3, Some("a"))
+        };
+        let message = "corrupted on the way, but still readable";
+        let mut png = encoded(&PNG_FILE, message, &options).unwrap();
+
+        // Flips bytes in the data of the second chunk, past the copies of the
+        // error correction header, leaving its CRC wrong
+        let start = png
+            .windows(4)
+            .enumerate()
+            .filter(|(_, window)| window == b"ruSt")
+            .map(|(i, _)| i + 4)
+            .nth(1)
+            .unwrap();
+        let length = u32::from_be_bytes(png[start - 8..start - 4].try_into().unwrap()) as usize;
+        for offset in [20, length / 2, length - 2] {
+            png[start + offset] ^= 0xff;
+        }
+
+        let png_ref = PngRef::parse_lenient(&png).unwrap();
+        assert_eq!(png_ref.diagnostics().len(), 1);
+        let (data, corrected) = find_message(&png_ref, &chunk_type(), Some("a")).unwrap();
+        assert_eq!(corrected, 3);
+        let decoded = open(&data, &DecodeOptions::default()).unwrap();
+        assert_eq!(decoded.data, message.as_bytes());
+    }
+}
@@ -0,0 +1,508 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{
+    ChaCha20Poly1305, Key, KeyInit, Nonce,
+    aead::{Aead, Payload},
+};
+use hkdf::Hkdf;
+use rand_core::{OsRng, RngCore};
+use sha2::Sha256;
+pub use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::Error;
+
+// Sealed payloads start with this header:
+//
+//   magic(4) version(1) scheme(1) scheme header(..) nonce(12) ciphertext+tag(..)
+//
+// Everything before the ciphertext is authenticated as associated data, so
+// tampering with the parameters is caught just like tampering with the message.
+pub const MAGIC: [u8; 4] = *b"PMEc";
+pub const VERSION: u8 = 1;
+
+const SCHEME_PASSPHRASE: u8 = 1;
+const SCHEME_RECIPIENTS: u8 = 2;
+
+const SALT_LEN: usize = 16;
+const CHECK_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+const WRAPPED_KEY_LEN: usize = KEY_LEN + TAG_LEN;
+
+// The cost parameters come from the envelope, so they are capped to keep a
+// crafted message from claiming gigabytes of memory or hours of work
+const MAX_M_COST: u32 = 256 * 1024;
+const MAX_T_COST: u32 = 16;
+const MAX_P_COST: u32 = 16;
+
+const IDENTITY_PREFIX: &str = "PNGME-SECRET-KEY-";
+const WRAP_INFO: &[u8] = b"pngme x25519 wrap";
+
+/// How a sealed payload was encrypted, and therefore what is needed to open it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    /// Key derived from a passphrase with Argon2id.
+    Passphrase,
+    /// Random key wrapped for one or more X25519 public keys.
+    Recipients,
+}
+
+/// Argon2id cost parameters used to derive a key from a passphrase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    /// Memory cost in KiB.
+    pub m_cost: u32,
+    /// Number of iterations.
+    pub t_cost: u32,
+    /// Degree of parallelism.
+    pub p_cost: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams {
+            m_cost: 19 * 1024,
+            t_cost: 2,
+            p_cost: 1,
+        }
+    }
+}
+
+/// Returns true if `data` looks like a sealed payload.
+pub fn is_sealed(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// Returns the scheme a sealed payload was encrypted with, if it is sealed at all.
+pub fn scheme(data: &[u8]) -> Option<Scheme> {
+    if !is_sealed(data) {
+        return None;
+    }
+    match data.get(MAGIC.len() + 1) {
+        Some(&SCHEME_PASSPHRASE) => Some(Scheme::Passphrase),
+        Some(&SCHEME_RECIPIENTS) => Some(Scheme::Recipients),
+        _ => None,
+    }
+}
+
+/// Encrypts `plaintext` with a key derived from `passphrase`.
+pub fn seal_with_passphrase(
+    plaintext: &[u8],
+    passphrase: &str,
+    params: KdfParams,
+) -> crate::Result<Vec<u8>> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+
+    let derived = derive(passphrase, &salt, params)?;
+    let (key, check) = derived.split_at(KEY_LEN);
+
+    let mut header: Vec<u8> = Vec::new();
+    header.extend_from_slice(&MAGIC);
+    header.push(VERSION);
+    header.push(SCHEME_PASSPHRASE);
+    header.extend_from_slice(&params.m_cost.to_be_bytes());
+    header.extend_from_slice(&params.t_cost.to_be_bytes());
+    header.extend_from_slice(&params.p_cost.to_be_bytes());
+    header.extend_from_slice(&salt);
+    header.extend_from_slice(check);
+    header.extend_from_slice(&nonce);
+
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let ciphertext = cipher
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad: &header,
+            },
+        )
+        .map_err(|_| Error::MalformedEnvelope("encryption failed".to_string()))?;
+
+    header.extend(ciphertext);
+    Ok(header)
+}
+
+/// Decrypts a payload produced by [`seal_with_passphrase`].
+///
+/// A wrong passphrase is reported as [`Error::WrongPassphrase`], any other
+/// modification of the sealed bytes as [`Error::TamperedPayload`].
+pub fn open_with_passphrase(sealed: &[u8], passphrase: &str) -> crate::Result<Vec<u8>> {
+    let mut reader = EnvelopeReader::new(sealed)?;
+    if reader.scheme != SCHEME_PASSPHRASE {
+        return Err(Error::MalformedEnvelope(format!(
+            "unsupported scheme {}",
+            reader.scheme
+        )));
+    }
+
+    let params = KdfParams {
+        m_cost: reader.u32()?,
+        t_cost: reader.u32()?,
+        p_cost: reader.u32()?,
+    };
+    if params.m_cost > MAX_M_COST || params.t_cost > MAX_T_COST || params.p_cost > MAX_P_COST {
+        return Err(Error::MalformedEnvelope(format!(
+            "key derivation costs too much ({} KiB, {} passes, {} lanes)",
+            params.m_cost, params.t_cost, params.p_cost
+        )));
+    }
+    let salt = reader.take(SALT_LEN)?;
+    let stored_check = reader.take(CHECK_LEN)?;
+    let nonce = reader.take(NONCE_LEN)?;
+    let (header, ciphertext) = sealed.split_at(reader.pos);
+
+    let derived = derive(passphrase, salt, params)?;
+    let (key, check) = derived.split_at(KEY_LEN);
+    if check != stored_check {
+        return Err(Error::WrongPassphrase);
+    }
+
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    cipher
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: header,
+            },
+        )
+        .map_err(|_| Error::TamperedPayload)
+}
+
+/// Encrypts `plaintext` so that any holder of a secret key matching one of
+/// `recipients` can open it.
+pub fn seal_for_recipients(plaintext: &[u8], recipients: &[PublicKey]) -> crate::Result<Vec<u8>> {
+    if recipients.is_empty() || recipients.len() > u16::MAX as usize {
+        return Err(Error::InvalidKey(format!(
+            "between 1 and {} recipients are required",
+            u16::MAX
+        )));
+    }
+
+    let mut file_key = [0u8; KEY_LEN];
+    OsRng.fill_bytes(&mut file_key);
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+
+    // A single ephemeral key is shared by all recipients, each of them gets
+    // their own copy of the file key wrapped with the derived shared secret.
+    let ephemeral = StaticSecret::random_from_rng(OsRng);
+    let ephemeral_public = PublicKey::from(&ephemeral);
+
+    let mut header: Vec<u8> = Vec::new();
+    header.extend_from_slice(&MAGIC);
+    header.push(VERSION);
+    header.push(SCHEME_RECIPIENTS);
+    header.extend_from_slice(ephemeral_public.as_bytes());
+    header.extend_from_slice(&(recipients.len() as u16).to_be_bytes());
+    for recipient in recipients {
+        let wrap_key = wrap_key(&ephemeral, recipient, &ephemeral_public, recipient)?;
+        let wrapped = ChaCha20Poly1305::new(Key::from_slice(&wrap_key))
+            .encrypt(&Nonce::default(), file_key.as_slice())
+            .map_err(|_| Error::MalformedEnvelope("key wrapping failed".to_string()))?;
+        header.extend(wrapped);
+    }
+    header.extend_from_slice(&nonce);
+
+    let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&file_key))
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad: &header,
+            },
+        )
+        .map_err(|_| Error::MalformedEnvelope("encryption failed".to_string()))?;
+
+    header.extend(ciphertext);
+    Ok(header)
+}
+
+/// Decrypts a payload produced by [`seal_for_recipients`] with one of the
+/// recipients' secret keys.
+///
+/// A key that is not among the recipients is reported as
+/// [`Error::NotARecipient`], any modification of the sealed bytes as
+/// [`Error::TamperedPayload`].
+pub fn open_with_identity(sealed: &[u8], identity: &StaticSecret) -> crate::Result<Vec<u8>> {
+    let mut reader = EnvelopeReader::new(sealed)?;
+    if reader.scheme != SCHEME_RECIPIENTS {
+        return Err(Error::MalformedEnvelope(format!(
+            "unsupported scheme {}",
+            reader.scheme
+        )));
+    }
+
+    let ephemeral_public: [u8; 32] = reader.take(32)?.try_into().unwrap();
+    let ephemeral_public = PublicKey::from(ephemeral_public);
+    let count = u16::from_be_bytes(reader.take(2)?.try_into().unwrap());
+
+    let identity_public = PublicKey::from(identity);
+    let wrap_key = wrap_key(
+        identity,
+        &ephemeral_public,
+        &ephemeral_public,
+        &identity_public,
+    )?;
+    let wrap_cipher = ChaCha20Poly1305::new(Key::from_slice(&wrap_key));
+    let mut file_key = None;
+    for _ in 0..count {
+        let wrapped = reader.take(WRAPPED_KEY_LEN)?;
+        if let Ok(key) = wrap_cipher.decrypt(&Nonce::default(), wrapped) {
+            file_key = Some(key);
+        }
+    }
+    let file_key = file_key.ok_or(Error::NotARecipient)?;
+
+    let nonce = reader.take(NONCE_LEN)?;
+    let (header, ciphertext) = sealed.split_at(reader.pos);
+
+    ChaCha20Poly1305::new(Key::from_slice(&file_key))
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: header,
+            },
+        )
+        .map_err(|_| Error::TamperedPayload)
+}
+
+/// Generates a new secret key for receiving messages.
+pub fn generate_identity() -> StaticSecret {
+    StaticSecret::random_from_rng(OsRng)
+}
+
+/// Formats a public key the way it is passed to `--recipient`.
+pub fn format_public_key(key: &PublicKey) -> String {
+    hex::encode(key.as_bytes())
+}
+
+/// Parses a public key as printed by [`format_public_key`].
+pub fn parse_public_key(s: &str) -> crate::Result<PublicKey> {
+    let bytes: [u8; 32] = hex::decode(s.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| Error::InvalidKey(format!("{:?} is not a public key", s)))?;
+    Ok(PublicKey::from(bytes))
+}
+
+/// Formats a secret key as the contents of an identity file.
+pub fn format_identity(identity: &StaticSecret) -> String {
+    format!(
+        "# public key: {}\n{}{}\n",
+        format_public_key(&PublicKey::from(identity)),
+        IDENTITY_PREFIX,
+        hex::encode(identity.as_bytes())
+    )
+}
+
+/// Parses the contents of an identity file written by [`format_identity`].
+pub fn parse_identity(s: &str) -> crate::Result<StaticSecret> {
+    let line = s
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .ok_or_else(|| Error::InvalidKey("identity file contains no key".to_string()))?;
+    let bytes: [u8; 32] = line
+        .strip_prefix(IDENTITY_PREFIX)
+        .and_then(|hex| hex::decode(hex).ok())
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| Error::InvalidKey("identity file contains no key".to_string()))?;
+    Ok(StaticSecret::from(bytes))
+}
+
+// Derives the key wrapping the file key for one recipient. Both the ephemeral
+// and the recipient public key are bound into the derivation.
+fn wrap_key(
+    secret: &StaticSecret,
+    peer: &PublicKey,
+    ephemeral: &PublicKey,
+    recipient: &PublicKey,
+) -> crate::Result<[u8; KEY_LEN]> {
+    let shared = secret.diffie_hellman(peer);
+    if !shared.was_contributory() {
+        return Err(Error::InvalidKey("low order public key".to_string()));
+    }
+
+    let salt: Vec<u8> = ephemeral
+        .as_bytes()
+        .iter()
+        .chain(recipient.as_bytes())
+        .copied()
+        .collect();
+
+    let mut key = [0u8; KEY_LEN];
+    Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes())
+        .expand(WRAP_INFO, &mut key)
+        .map_err(|_| Error::MalformedEnvelope("key derivation failed".to_string()))?;
+    Ok(key)
+}
+
+// Derives the encryption key followed by a key check value, so a wrong
+// passphrase can be told apart from a modified ciphertext.
+fn derive(passphrase: &str, salt: &[u8], params: KdfParams) -> crate::Result<Vec<u8>> {
+    let params = Params::new(
+        params.m_cost,
+        params.t_cost,
+        params.p_cost,
+        Some(KEY_LEN + CHECK_LEN),
+    )
+    .map_err(|err| Error::MalformedEnvelope(format!("invalid Argon2 parameters: {}", err)))?;
+
+    let mut derived = vec![0u8; KEY_LEN + CHECK_LEN];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut derived)
+        .map_err(|err| Error::MalformedEnvelope(format!("key derivation failed: {}", err)))?;
+    Ok(derived)
+}
+
+// Small cursor over the envelope header that reports short input as a
+// malformed envelope instead of panicking.
+struct EnvelopeReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    scheme: u8,
+}
+
+impl<'a> EnvelopeReader<'a> {
+    fn new(data: &'a [u8]) -> crate::Result<Self> {
+        if !is_sealed(data) || data.len() < MAGIC.len() + 2 {
+            return Err(Error::MalformedEnvelope("missing header".to_string()));
+        }
+        let version = data[MAGIC.len()];
+        if version != VERSION {
+            return Err(Error::MalformedEnvelope(format!(
+                "unsupported version {}",
+                version
+            )));
+        }
+        Ok(EnvelopeReader {
+            data,
+            pos: MAGIC.len() + 2,
+            scheme: data[MAGIC.len() + 1],
+        })
+    }
+
+    fn take(&mut self, n: usize) -> crate::Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + n)
+            .ok_or_else(|| Error::MalformedEnvelope("header is truncated".to_string()))?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> crate::Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cheap parameters so the tests don't spend their time in Argon2
+    const TEST_PARAMS: KdfParams = KdfParams {
+        m_cost: 64,
+        t_cost: 1,
+        p_cost: 1,
+    };
+
+    #[test]
+    fn test_seal_and_open() {
+        let sealed = seal_with_passphrase(b"Hello, friend!", "hunter2", TEST_PARAMS).unwrap();
+        assert!(is_sealed(&sealed));
+
+        let opened = open_with_passphrase(&sealed, "hunter2").unwrap();
+        assert_eq!(opened, b"Hello, friend!");
+    }
+
+    #[test]
+    fn test_wrong_passphrase() {
+        let sealed = seal_with_passphrase(b"Hello, friend!", "hunter2", TEST_PARAMS).unwrap();
+
+        let opened = open_with_passphrase(&sealed, "hunter3");
+        assert!(matches!(opened, Err(Error::WrongPassphrase)));
+    }
+
+    #[test]
+    fn test_tampered_ciphertext() {
+        let mut sealed = seal_with_passphrase(b"Hello, friend!", "hunter2", TEST_PARAMS).unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+
+        let opened = open_with_passphrase(&sealed, "hunter2");
+        assert!(matches!(opened, Err(Error::TamperedPayload)));
+    }
+
+    #[test]
+    fn test_seal_for_recipients() {
+        let alice = generate_identity();
+        let bob = generate_identity();
+        let recipients = [PublicKey::from(&alice), PublicKey::from(&bob)];
+
+        let sealed = seal_for_recipients(b"Hello, friends!", &recipients).unwrap();
+        assert_eq!(scheme(&sealed), Some(Scheme::Recipients));
+
+        assert_eq!(
+            open_with_identity(&sealed, &alice).unwrap(),
+            b"Hello, friends!"
+        );
+        assert_eq!(
+            open_with_identity(&sealed, &bob).unwrap(),
+            b"Hello, friends!"
+        );
+    }
+
+    #[test]
+    fn test_not_a_recipient() {
+        let alice = generate_identity();
+        let eve = generate_identity();
+
+        let sealed = seal_for_recipients(b"Hello, friend!", &[PublicKey::from(&alice)]).unwrap();
+
+        let opened = open_with_identity(&sealed, &eve);
+        assert!(matches!(opened, Err(Error::NotARecipient)));
+    }
+
+    #[test]
+    fn test_identity_round_trip() {
+        let identity = generate_identity();
+        let public = format_public_key(&PublicKey::from(&identity));
+
+        let parsed = parse_identity(&format_identity(&identity)).unwrap();
+        assert_eq!(parsed.to_bytes(), identity.to_bytes());
+        assert_eq!(
+            parse_public_key(&public).unwrap(),
+            PublicKey::from(&identity)
+        );
+        assert!(parse_public_key("not a key").is_err());
+    }
+
+    #[test]
+    fn test_truncated_header() {
+        let sealed = seal_with_passphrase(b"Hello, friend!", "hunter2", TEST_PARAMS).unwrap();
+
+        let opened = open_with_passphrase(&sealed[..20], "hunter2");
+        assert!(matches!(opened, Err(Error::MalformedEnvelope(_))));
+    }
+
+    #[test]
+    fn test_rejects_costly_params() {
+        let sealed = seal_with_passphrase(b"Hello, friend!", "hunter2", TEST_PARAMS).unwrap();
+        // m_cost follows the magic, version and scheme
+        for (field, cost) in [(0, MAX_M_COST + 1), (1, MAX_T_COST + 1), (2, u32::MAX)] {
+            let mut crafted = sealed.clone();
+            let at = MAGIC.len() + 2 + field * 4;
+            crafted[at..at + 4].copy_from_slice(&cost.to_be_bytes());
+
+            let opened = open_with_passphrase(&crafted, "hunter2");
+            assert!(matches!(opened, Err(Error::MalformedEnvelope(_))));
+        }
+    }
+}
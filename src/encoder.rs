@@ -0,0 +1,382 @@
+use std::{io::Write, str::FromStr};
+
+use flate2::{Compression, write::ZlibEncoder};
+
+use crate::{
+    Error,
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    ihdr::{ColorType, Ihdr},
+    pixels::{ADAM7, Image, Samples, filter_bpp, paeth, pass_size, row_bytes},
+    png::Png,
+};
+
+/// The five scanline filters defined by the PNG specification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    None = 0,
+    Sub = 1,
+    Up = 2,
+    Average = 3,
+    Paeth = 4,
+}
+
+impl Filter {
+    const ALL: [Filter; 5] = [
+        Filter::None,
+        Filter::Sub,
+        Filter::Up,
+        Filter::Average,
+        Filter::Paeth,
+    ];
+}
+
+/// How a filter is chosen for each scanline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterStrategy {
+    /// Try every filter on each row and keep the one whose output has the
+    /// smallest sum of absolute values, the heuristic the specification
+    /// suggests. Indexed and sub-byte images always use [`Filter::None`].
+    Adaptive,
+    /// Use the same filter for every row.
+    Fixed(Filter),
+}
+
+/// Settings for [`Png::encode_image`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncoderSettings {
+    pub filter: FilterStrategy,
+    /// Deflate level, from 0 (store) to 9 (best).
+    pub compression_level: u32,
+    /// Largest amount of data written in one IDAT chunk.
+    pub max_idat_size: usize,
+    /// Write the scanlines with Adam7 interlacing.
+    pub interlace: bool,
+}
+
+impl Default for EncoderSettings {
+    fn default() -> Self {
+        EncoderSettings {
+            filter: FilterStrategy::Adaptive,
+            compression_level: 6,
+            max_idat_size: 8192,
+            interlace: false,
+        }
+    }
+}
+
+impl Png {
+    /// Encodes `image` into a new IDAT sequence that replaces the existing
+    /// one, in place. IHDR (and PLTE for indexed images) are rewritten to
+    /// describe the image, every other chunk is kept as is.
+    pub fn encode_image(&mut self, image: &Image, settings: &EncoderSettings) -> crate::Result<()> {
+        if settings.compression_level > 9 {
+            return Err(Error::InvalidImageData(format!(
+                "compression level {} is not between 0 and 9",
+                settings.compression_level
+            )));
+        }
+        if settings.max_idat_size == 0 || settings.max_idat_size > i32::MAX as usize {
+            return Err(Error::InvalidImageData(format!(
+                "invalid maximum IDAT size {}",
+                settings.max_idat_size
+            )));
+        }
+
+        let ihdr = Ihdr {
+            width: image.width(),
+            height: image.height(),
+            bit_depth: image.bit_depth(),
+            color_type: image.color_type(),
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: settings.interlace as u8,
+        };
+        ihdr.validate()?;
+
+        let scanlines = filter_image(image, &ihdr, settings.filter);
+        let mut encoder =
+            ZlibEncoder::new(Vec::new(), Compression::new(settings.compression_level));
+        encoder.write_all(&scanlines)?;
+        let compressed = encoder.finish()?;
+
+        self.replace_chunk(Chunk::new(ChunkType::from_str("IHDR")?, ihdr.as_bytes()))?;
+        match (image.color_type(), image.palette()) {
+            (ColorType::Indexed, Some(palette)) => {
+                let data = palette.iter().flatten().copied().collect();
+                self.replace_chunk(Chunk::new(ChunkType::from_str("PLTE")?, data))?;
+            }
+            (ColorType::Indexed, None) if self.chunk_by_type("PLTE").is_none() => {
+                return Err(Error::InvalidImageData(
+                    "indexed image has no palette".to_string(),
+                ));
+            }
+            // A palette is not allowed in grayscale images
+            (ColorType::Grayscale | ColorType::GrayscaleAlpha, _) => {
+                self.retain_chunks(|c| c.chunk_type().to_string() != "PLTE");
+            }
+            _ => {}
+        }
+
+        let mut index = self
+            .chunks()
+            .iter()
+            .position(|c| c.chunk_type().to_string() == "IDAT");
+        self.retain_chunks(|c| c.chunk_type().to_string() != "IDAT");
+        for data in compressed.chunks(settings.max_idat_size) {
+            let idat = Chunk::new(ChunkType::from_str("IDAT")?, data.to_vec());
+            match index.as_mut() {
+                Some(i) => {
+                    self.insert_at(*i, idat)?;
+                    *i += 1;
+                }
+                None => {
+                    self.insert_chunk(idat)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Swaps the first chunk of the same type for `chunk`, or inserts it
+    fn replace_chunk(&mut self, chunk: Chunk) -> crate::Result<()> {
+        let chunk_type = chunk.chunk_type().to_string();
+        match self
+            .chunks()
+            .iter()
+            .position(|c| c.chunk_type().to_string() == chunk_type)
+        {
+            Some(index) => {
+                self.retain_chunks(|c| c.chunk_type().to_string() != chunk_type);
+                self.insert_at(index, chunk)
+            }
+            None => self.insert_chunk(chunk).map(|_| ()),
+        }
+    }
+}
+
+// Packs and filters every scanline, pass by pass for interlaced images
+fn filter_image(image: &Image, ihdr: &Ihdr, strategy: FilterStrategy) -> Vec<u8> {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let passes: Vec<(usize, usize, usize, usize)> = if ihdr.is_interlaced() {
+        ADAM7.to_vec()
+    } else {
+        vec![(0, 0, 1, 1)]
+    };
+    let strategy = match (strategy, ihdr.color_type, ihdr.bit_depth) {
+        (FilterStrategy::Adaptive, ColorType::Indexed, _) => FilterStrategy::Fixed(Filter::None),
+        (FilterStrategy::Adaptive, _, depth) if depth < 8 => FilterStrategy::Fixed(Filter::None),
+        (strategy, _, _) => strategy,
+    };
+    let bpp = filter_bpp(ihdr);
+
+    let mut out = Vec::new();
+    for (pass, &(x0, y0, dx, dy)) in passes.iter().enumerate() {
+        let (pass_width, pass_height) = if ihdr.is_interlaced() {
+            pass_size(pass, width, height)
+        } else {
+            (width, height)
+        };
+        if pass_width == 0 || pass_height == 0 {
+            continue;
+        }
+
+        let mut previous = vec![0u8; row_bytes(ihdr, pass_width)];
+        for py in 0..pass_height {
+            let y = y0 + py * dy;
+            let xs = (0..pass_width).map(|px| x0 + px * dx);
+            let row = pack_row(image, y, xs);
+
+            let filter = match strategy {
+                FilterStrategy::Fixed(filter) => filter,
+                FilterStrategy::Adaptive => *Filter::ALL
+                    .iter()
+                    .min_by_key(|&&f| score(&apply_filter(f, bpp, &previous, &row)))
+                    .unwrap(),
+            };
+            out.push(filter as u8);
+            out.extend(apply_filter(filter, bpp, &previous, &row));
+            previous = row;
+        }
+    }
+    out
+}
+
+// Packs the samples of the given pixels of row `y` at the image bit depth
+fn pack_row(image: &Image, y: usize, xs: impl Iterator<Item = usize>) -> Vec<u8> {
+    let channels = image.channels();
+    let width = image.width() as usize;
+    let bit_depth = image.bit_depth() as usize;
+    let samples = image.samples();
+
+    let mut row = Vec::new();
+    let mut bits = 0usize;
+    for x in xs {
+        for c in 0..channels {
+            let value = samples.get((y * width + x) * channels + c);
+            match samples {
+                Samples::U16(_) => row.extend_from_slice(&value.to_be_bytes()),
+                Samples::U8(_) if bit_depth == 8 => row.push(value as u8),
+                Samples::U8(_) => {
+                    if bits.is_multiple_of(8) {
+                        row.push(0);
+                    }
+                    let shift = 8 - bit_depth - bits % 8;
+                    *row.last_mut().unwrap() |= (value as u8) << shift;
+                    bits += bit_depth;
+                }
+            }
+        }
+    }
+    row
+}
+
+fn apply_filter(filter: Filter, bpp: usize, previous: &[u8], row: &[u8]) -> Vec<u8> {
+    let left = |i: usize| if i >= bpp { row[i - bpp] } else { 0 };
+    let upper_left = |i: usize| if i >= bpp { previous[i - bpp] } else { 0 };
+
+    (0..row.len())
+        .map(|i| match filter {
+            Filter::None => row[i],
+            Filter::Sub => row[i].wrapping_sub(left(i)),
+            Filter::Up => row[i].wrapping_sub(previous[i]),
+            Filter::Average => {
+                row[i].wrapping_sub(((left(i) as u16 + previous[i] as u16) / 2) as u8)
+            }
+            Filter::Paeth => row[i].wrapping_sub(paeth(left(i), previous[i], upper_left(i))),
+        })
+        .collect()
+}
+
+// Minimum sum of absolute differences, treating filtered bytes as signed
+fn score(filtered: &[u8]) -> u64 {
+    filtered
+        .iter()
+        .map(|&b| (b as i8).unsigned_abs() as u64)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::PNG_FILE;
+
+    fn chunk_types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect()
+    }
+
+    fn gradient(color_type: ColorType, bit_depth: u8) -> Image {
+        let (width, height) = (13, 7);
+        let count = width * height * color_type.channels() as usize;
+        let max = (1u32 << bit_depth) - 1;
+        let values = (0..count).map(|i| ((i * 37 + i / 5) as u32 % (max + 1)) as u16);
+        let samples = match bit_depth {
+            16 => Samples::U16(values.map(|v| v.wrapping_mul(257)).collect()),
+            _ => Samples::U8(values.map(|v| v as u8).collect()),
+        };
+        let palette = (color_type == ColorType::Indexed)
+            .then(|| (0..=max).map(|i| [i as u8, 0, 255 - i as u8]).collect());
+        Image::new(
+            width as u32,
+            height as u32,
+            color_type,
+            bit_depth,
+            palette,
+            samples,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_round_trip_every_format() {
+        for color_type in [
+            ColorType::Grayscale,
+            ColorType::Rgb,
+            ColorType::Indexed,
+            ColorType::GrayscaleAlpha,
+            ColorType::Rgba,
+        ] {
+            for &bit_depth in color_type.allowed_bit_depths() {
+                for interlace in [false, true] {
+                    let image = gradient(color_type, bit_depth);
+                    let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+                    let settings = EncoderSettings {
+                        interlace,
+                        ..Default::default()
+                    };
+                    png.encode_image(&image, &settings).unwrap();
+
+                    let bytes = png.as_bytes();
+                    let decoded = Png::try_from(bytes.as_slice())
+                        .unwrap()
+                        .decode_image()
+                        .unwrap();
+                    assert_eq!(
+                        decoded, image,
+                        "{} {}-bit interlaced: {}",
+                        color_type, bit_depth, interlace
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_every_fixed_filter() {
+        let image = gradient(ColorType::Rgb, 8);
+        for filter in Filter::ALL {
+            let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+            let settings = EncoderSettings {
+                filter: FilterStrategy::Fixed(filter),
+                ..Default::default()
+            };
+            png.encode_image(&image, &settings).unwrap();
+
+            assert_eq!(png.decode_image().unwrap(), image, "{:?}", filter);
+        }
+    }
+
+    #[test]
+    fn test_splits_idat_and_keeps_ancillary_chunks() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let image = png.decode_image().unwrap();
+        let settings = EncoderSettings {
+            max_idat_size: 1000,
+            compression_level: 9,
+            ..Default::default()
+        };
+        png.encode_image(&image, &settings).unwrap();
+
+        let idats: Vec<usize> = png
+            .chunks()
+            .iter()
+            .filter(|c| c.chunk_type().to_string() == "IDAT")
+            .map(|c| c.data().len())
+            .collect();
+        assert!(idats.len() > 1);
+        assert!(idats.iter().all(|&len| len <= 1000));
+        assert_eq!(
+            chunk_types(&png)
+                .into_iter()
+                .filter(|t| t != "IDAT")
+                .collect::<Vec<_>>(),
+            vec!["IHDR", "sRGB", "gAMA", "pHYs", "RuSt", "IEND"]
+        );
+        assert_eq!(png.decode_image().unwrap(), image);
+    }
+
+    #[test]
+    fn test_invalid_settings() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let image = png.decode_image().unwrap();
+        let settings = EncoderSettings {
+            compression_level: 10,
+            ..Default::default()
+        };
+        assert!(png.encode_image(&image, &settings).is_err());
+    }
+}
@@ -0,0 +1,237 @@
+use std::{fmt::Display, io, string::FromUtf8Error};
+
+/// Everything that can go wrong while reading, editing or writing a PNG.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing the underlying file failed.
+    Io(io::Error),
+    /// The first 8 bytes are not the PNG signature.
+    BadSignature,
+    /// The input ended before a whole chunk could be read.
+    TruncatedChunk { offset: usize, needed: usize },
+    /// A chunk announces more than the 2^31 - 1 bytes of data PNG allows.
+    ChunkTooLong { offset: usize, length: u32 },
+    /// The CRC stored after a chunk does not match its type and data.
+    CrcMismatch {
+        chunk_type: String,
+        stored: u32,
+        computed: u32,
+        offset: usize,
+    },
+    /// A chunk type is not made of four ASCII letters.
+    InvalidChunkType(String),
+    /// No chunk of the requested type exists in the file.
+    ChunkNotFound(String),
+    /// A chunk's data was expected to be UTF-8 but is not.
+    NonUtf8Payload(FromUtf8Error),
+    /// The message is encrypted but the key needed to open it was not supplied.
+    KeyRequired(&'static str),
+    /// The supplied passphrase does not match the one used to encrypt.
+    WrongPassphrase,
+    /// The encrypted message was modified after it was sealed.
+    TamperedPayload,
+    /// None of the recipients the message was encrypted for match the identity.
+    NotARecipient,
+    /// A public key or identity file could not be parsed.
+    InvalidKey(String),
+    /// The signature chunk was forged or damaged.
+    InvalidSignature,
+    /// The signature is genuine but chunks changed since the file was signed.
+    ContentModified { changes: usize },
+    /// The IHDR chunk is missing fields or breaks the specification.
+    InvalidIhdr(String),
+    /// A textual chunk is malformed or its keyword breaks the specification.
+    InvalidText(String),
+    /// A chunk cannot be placed where it was requested.
+    ChunkOrder(String),
+    /// The file breaks structural rules of the PNG specification.
+    ValidationFailed { errors: usize },
+    /// The compressed pixel data could not be decoded.
+    InvalidImageData(String),
+    /// The payload does not fit in the pixels of the image.
+    PayloadTooLarge { size: usize, capacity: usize },
+    /// LSB embedding settings do not suit the image, or nothing is hidden in it.
+    InvalidLsb(String),
+    /// The analysis found likely hidden data in some of the files.
+    HiddenDataSuspected { files: usize },
+    /// The header or body of a packed payload is damaged.
+    MalformedPayload(String),
+    /// The fragments of a split payload are damaged, missing or repeated.
+    InvalidFragments(String),
+    /// A message label is malformed or breaks the naming rules.
+    InvalidLabel(String),
+    /// A message with this label already exists in the chunk type.
+    DuplicateLabel(String),
+    /// Error correction data is malformed or too damaged to recover from.
+    ErrorCorrection(String),
+    /// The encrypted envelope header could not be understood.
+    MalformedEnvelope(String),
+}
+
+impl Error {
+    /// Process exit code used by the CLI for this kind of error. Each kind
+    /// has its own, and none is 1 or 2, which clap uses for usage errors.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Io(_) => 31,
+            Error::BadSignature => 3,
+            Error::TruncatedChunk { .. } => 4,
+            Error::CrcMismatch { .. } => 5,
+            Error::InvalidChunkType(_) => 6,
+            Error::ChunkNotFound(_) => 7,
+            Error::NonUtf8Payload(_) => 8,
+            Error::KeyRequired(_) => 9,
+            Error::WrongPassphrase => 10,
+            Error::TamperedPayload => 11,
+            Error::MalformedEnvelope(_) => 12,
+            Error::NotARecipient => 13,
+            Error::InvalidKey(_) => 14,
+            Error::InvalidSignature => 15,
+            Error::ContentModified { .. } => 16,
+            Error::InvalidIhdr(_) => 17,
+            Error::InvalidText(_) => 18,
+            Error::ChunkOrder(_) => 19,
+            Error::ValidationFailed { .. } => 20,
+            Error::InvalidImageData(_) => 21,
+            Error::PayloadTooLarge { .. } => 22,
+            Error::InvalidLsb(_) => 23,
+            Error::HiddenDataSuspected { .. } => 24,
+            Error::MalformedPayload(_) => 25,
+            Error::InvalidFragments(_) => 26,
+            Error::InvalidLabel(_) => 27,
+            Error::DuplicateLabel(_) => 28,
+            Error::ErrorCorrection(_) => 29,
+            Error::ChunkTooLong { .. } => 30,
+        }
+    }
+
+    // Shifts the offset of positional errors by `base`, used when a chunk was
+    // parsed from a sub-slice of a larger file.
+    pub(crate) fn at_offset(self, base: usize) -> Error {
+        match self {
+            Error::TruncatedChunk { offset, needed } => Error::TruncatedChunk {
+                offset: offset + base,
+                needed,
+            },
+            Error::ChunkTooLong { offset, length } => Error::ChunkTooLong {
+                offset: offset + base,
+                length,
+            },
+            Error::CrcMismatch {
+                chunk_type,
+                stored,
+                computed,
+                offset,
+            } => Error::CrcMismatch {
+                chunk_type,
+                stored,
+                computed,
+                offset: offset + base,
+            },
+            other => other,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::BadSignature => write!(f, "Signature Header mismatch. Invalid PNG file."),
+            Error::TruncatedChunk { offset, needed } => write!(
+                f,
+                "Truncated chunk at offset {}: {} more bytes needed.",
+                offset, needed
+            ),
+            Error::ChunkTooLong { offset, length } => write!(
+                f,
+                "Chunk at offset {} is too long: {} bytes of data.",
+                offset, length
+            ),
+            Error::CrcMismatch {
+                chunk_type,
+                stored,
+                computed,
+                offset,
+            } => write!(
+                f,
+                "CRC mismatch in {} chunk at offset {}: stored {:08X}, computed {:08X}.",
+                chunk_type, offset, stored, computed
+            ),
+            Error::InvalidChunkType(chunk_type) => {
+                write!(f, "Invalid chunk type: {:?}.", chunk_type)
+            }
+            Error::ChunkNotFound(chunk_type) => {
+                write!(f, "No chunk of type {} was found in the file.", chunk_type)
+            }
+            Error::NonUtf8Payload(err) => write!(f, "Chunk data is not valid UTF-8: {}", err),
+            Error::KeyRequired(key) => {
+                write!(f, "The message is encrypted, {} is required.", key)
+            }
+            Error::WrongPassphrase => write!(f, "Wrong passphrase."),
+            Error::TamperedPayload => {
+                write!(f, "The encrypted message has been tampered with.")
+            }
+            Error::NotARecipient => {
+                write!(f, "The message was not encrypted for this identity.")
+            }
+            Error::InvalidKey(reason) => write!(f, "Invalid key: {}.", reason),
+            Error::InvalidSignature => write!(f, "The signature is not valid."),
+            Error::ContentModified { changes } => write!(
+                f,
+                "The file was modified since it was signed: {} chunk(s) changed.",
+                changes
+            ),
+            Error::InvalidIhdr(reason) => write!(f, "Invalid IHDR chunk: {}.", reason),
+            Error::InvalidText(reason) => write!(f, "Invalid text chunk: {}.", reason),
+            Error::ChunkOrder(reason) => write!(f, "Invalid chunk placement: {}.", reason),
+            Error::ValidationFailed { errors } => {
+                write!(f, "The file is not a valid PNG: {} error(s) found.", errors)
+            }
+            Error::InvalidImageData(reason) => write!(f, "Invalid image data: {}.", reason),
+            Error::PayloadTooLarge { size, capacity } => write!(
+                f,
+                "The payload is {} bytes but the image can only hold {} bytes.",
+                size, capacity
+            ),
+            Error::InvalidLsb(reason) => write!(f, "LSB embedding failed: {}.", reason),
+            Error::HiddenDataSuspected { files } => {
+                write!(f, "Hidden data is likely in {} file(s).", files)
+            }
+            Error::MalformedPayload(reason) => write!(f, "Malformed payload: {}.", reason),
+            Error::InvalidFragments(reason) => write!(f, "Invalid fragments: {}.", reason),
+            Error::InvalidLabel(reason) => write!(f, "Invalid label: {}.", reason),
+            Error::DuplicateLabel(label) => write!(
+                f,
+                "A message labeled {:?} already exists, remove it first.",
+                label
+            ),
+            Error::ErrorCorrection(reason) => write!(f, "Error correction failed: {}.", reason),
+            Error::MalformedEnvelope(reason) => {
+                write!(f, "Malformed encrypted message: {}.", reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::NonUtf8Payload(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<FromUtf8Error> for Error {
+    fn from(err: FromUtf8Error) -> Self {
+        Error::NonUtf8Payload(err)
+    }
+}
@@ -0,0 +1,383 @@
+use crate::Error;
+
+// Protected data starts with this header, written three times so a damaged
+// copy is outvoted, followed by Reed-Solomon codewords:
+//
+//   magic(4) version(1) strength(1) codewords(..)
+//
+// The codewords carry the original length (4 bytes) and the data, split into
+// blocks of at most 255 bytes, each able to correct `strength` corrupted
+// bytes. Blocks are interleaved byte by byte, so a burst of corrupted bytes
+// is shared between all of them.
+pub const MAGIC: [u8; 4] = *b"PMEr";
+pub const VERSION: u8 = 1;
+
+/// Largest number of corrupted bytes a block can be protected against.
+pub const MAX_STRENGTH: u8 = 127;
+
+const HEADER_COPY_LEN: usize = MAGIC.len() + 2;
+const HEADER_LEN: usize = 3 * HEADER_COPY_LEN;
+const BLOCK_LEN: usize = 255;
+
+/// Returns true if `data` starts with an error correction header, even a
+/// slightly damaged one.
+pub fn is_protected(data: &[u8]) -> bool {
+    header(data).is_some_and(|header| header.starts_with(&MAGIC))
+}
+
+// Each byte of the header is the one at least two of the copies agree on
+fn header(data: &[u8]) -> Option<[u8; HEADER_COPY_LEN]> {
+    let copies = data.get(..HEADER_LEN)?;
+    let mut header = [0; HEADER_COPY_LEN];
+    for (i, byte) in header.iter_mut().enumerate() {
+        let (a, b, c) = (
+            copies[i],
+            copies[i + HEADER_COPY_LEN],
+            copies[i + 2 * HEADER_COPY_LEN],
+        );
+        *byte = if a == b || a == c {
+            a
+        } else if b == c {
+            b
+        } else {
+            return None;
+        };
+    }
+    Some(header)
+}
+
+/// Adds enough redundancy to `data` to correct up to `strength` corrupted
+/// bytes in every 255.
+pub fn protect(data: &[u8], strength: u8) -> crate::Result<Vec<u8>> {
+    if strength == 0 || strength > MAX_STRENGTH {
+        return Err(Error::ErrorCorrection(format!(
+            "the strength must be between 1 and {}, not {}",
+            MAX_STRENGTH, strength
+        )));
+    }
+    let length = u32::try_from(data.len()).map_err(|_| {
+        Error::ErrorCorrection(format!("{} bytes are too many to protect", data.len()))
+    })?;
+    let mut message = length.to_be_bytes().to_vec();
+    message.extend_from_slice(data);
+
+    let parity = 2 * strength as usize;
+    let blocks = message.len().div_ceil(BLOCK_LEN - parity);
+    let block_data = message.len().div_ceil(blocks);
+    message.resize(blocks * block_data, 0);
+
+    let generator = generator(parity);
+    let codewords: Vec<Vec<u8>> = message
+        .chunks(block_data)
+        .map(|block| encode_block(block, &generator))
+        .collect();
+
+    let mut protected = Vec::with_capacity(HEADER_LEN + blocks * (block_data + parity));
+    for _ in 0..3 {
+        protected.extend_from_slice(&MAGIC);
+        protected.push(VERSION);
+        protected.push(strength);
+    }
+    for i in 0..block_data + parity {
+        protected.extend(codewords.iter().map(|codeword| codeword[i]));
+    }
+    Ok(protected)
+}
+
+/// Returns the data protected by [`protect`], with the number of corrupted
+/// bytes that were corrected.
+pub fn recover(protected: &[u8]) -> crate::Result<(Vec<u8>, usize)> {
+    let header = header(protected)
+        .filter(|header| header.starts_with(&MAGIC))
+        .ok_or_else(|| Error::ErrorCorrection("missing header".to_string()))?;
+    if header[MAGIC.len()] != VERSION {
+        return Err(Error::ErrorCorrection(format!(
+            "unsupported version {}",
+            header[MAGIC.len()]
+        )));
+    }
+    let strength = header[MAGIC.len() + 1];
+    let parity = 2 * strength as usize;
+    let body = &protected[HEADER_LEN..];
+
+    // Blocks are as few as possible, so their number follows from the size
+    let blocks = body.len().div_ceil(BLOCK_LEN);
+    if strength == 0 || strength > MAX_STRENGTH || blocks == 0 || !body.len().is_multiple_of(blocks)
+    {
+        return Err(Error::ErrorCorrection("malformed header".to_string()));
+    }
+    let block_len = body.len() / blocks;
+    if block_len <= parity {
+        return Err(Error::ErrorCorrection("malformed header".to_string()));
+    }
+
+    let mut message = Vec::with_capacity(blocks * (block_len - parity));
+    let mut corrected = 0;
+    for b in 0..blocks {
+        let mut codeword: Vec<u8> = body.iter().skip(b).step_by(blocks).copied().collect();
+        corrected += correct_block(&mut codeword, parity).ok_or_else(|| {
+            Error::ErrorCorrection(format!(
+                "block {} of {} has more than {} corrupted bytes",
+                b + 1,
+                blocks,
+                strength
+            ))
+        })?;
+        message.extend_from_slice(&codeword[..block_len - parity]);
+    }
+
+    let length = message
+        .first_chunk::<4>()
+        .map(|length| u32::from_be_bytes(*length) as usize)
+        .ok_or_else(|| Error::ErrorCorrection("truncated".to_string()))?;
+    if 4 + length > message.len() {
+        return Err(Error::ErrorCorrection(format!(
+            "expected {} bytes, found {}",
+            length,
+            message.len() - 4
+        )));
+    }
+    Ok((message[4..4 + length].to_vec(), corrected))
+}
+
+// GF(2^8) arithmetic with the polynomial x^8 + x^4 + x^3 + x^2 + 1, whose
+// generator is 2. EXP is doubled so products need no modulo.
+const TABLES: ([u8; 512], [u8; 256]) = tables();
+const EXP: [u8; 512] = TABLES.0;
+const LOG: [u8; 256] = TABLES.1;
+
+const fn tables() -> ([u8; 512], [u8; 256]) {
+    let (mut exp, mut log) = ([0; 512], [0; 256]);
+    let mut x: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        exp[i] = x as u8;
+        log[x as usize] = i as u8;
+        x <<= 1;
+        if x & 0x100 != 0 {
+            x ^= 0x11d;
+        }
+        i += 1;
+    }
+    while i < 512 {
+        exp[i] = exp[i - 255];
+        i += 1;
+    }
+    (exp, log)
+}
+
+fn mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    EXP[LOG[a as usize] as usize + LOG[b as usize] as usize]
+}
+
+fn div(a: u8, b: u8) -> u8 {
+    if a == 0 {
+        return 0;
+    }
+    EXP[LOG[a as usize] as usize + 255 - LOG[b as usize] as usize]
+}
+
+fn alpha_pow(power: usize) -> u8 {
+    EXP[power % 255]
+}
+
+// Polynomials are stored highest degree first for encoding, lowest first
+// for decoding, as the algorithms are usually written.
+
+// (x - a^0)(x - a^1)...(x - a^(parity - 1))
+fn generator(parity: usize) -> Vec<u8> {
+    let mut generator = vec![1];
+    for i in 0..parity {
+        let root = alpha_pow(i);
+        let mut next = vec![0; generator.len() + 1];
+        for (j, &coefficient) in generator.iter().enumerate() {
+            next[j] ^= coefficient;
+            next[j + 1] ^= mul(coefficient, root);
+        }
+        generator = next;
+    }
+    generator
+}
+
+// The block followed by the remainder of its division by the generator
+fn encode_block(block: &[u8], generator: &[u8]) -> Vec<u8> {
+    let parity = generator.len() - 1;
+    let mut codeword = block.to_vec();
+    codeword.resize(block.len() + parity, 0);
+    for i in 0..block.len() {
+        let coefficient = codeword[i];
+        if coefficient != 0 {
+            for (j, &g) in generator.iter().enumerate().skip(1) {
+                codeword[i + j] ^= mul(g, coefficient);
+            }
+        }
+    }
+    codeword[..block.len()].copy_from_slice(block);
+    codeword
+}
+
+fn syndromes_of(codeword: &[u8], parity: usize) -> Vec<u8> {
+    (0..parity)
+        .map(|i| {
+            let root = alpha_pow(i);
+            codeword.iter().fold(0, |acc, &c| mul(acc, root) ^ c)
+        })
+        .collect()
+}
+
+// Fixes the codeword in place and returns how many bytes were wrong, or None
+// if there are too many errors to tell
+fn correct_block(codeword: &mut [u8], parity: usize) -> Option<usize> {
+    let syndromes = syndromes_of(codeword, parity);
+    if syndromes.iter().all(|&s| s == 0) {
+        return Some(0);
+    }
+
+    // Berlekamp-Massey finds the error locator
+    let (mut locator, mut previous) = (vec![1u8], vec![1u8]);
+    let (mut errors, mut shift, mut last_discrepancy) = (0, 1, 1u8);
+    for r in 0..parity {
+        let discrepancy = (1..=errors.min(locator.len() - 1)).fold(syndromes[r], |acc, i| {
+            acc ^ mul(locator[i], syndromes[r - i])
+        });
+        if discrepancy == 0 {
+            shift += 1;
+            continue;
+        }
+        let scale = div(discrepancy, last_discrepancy);
+        let mut next = locator.clone();
+        next.resize(next.len().max(previous.len() + shift), 0);
+        for (i, &p) in previous.iter().enumerate() {
+            next[i + shift] ^= mul(scale, p);
+        }
+        if 2 * errors <= r {
+            previous = std::mem::replace(&mut locator, next);
+            errors = r + 1 - errors;
+            last_discrepancy = discrepancy;
+            shift = 1;
+        } else {
+            locator = next;
+            shift += 1;
+        }
+    }
+    while locator.last() == Some(&0) {
+        locator.pop();
+    }
+    if locator.len() - 1 != errors || 2 * errors > parity {
+        return None;
+    }
+
+    // Chien search: position i holds the coefficient of x^(n - 1 - i)
+    let n = codeword.len();
+    let evaluate = |poly: &[u8], x: u8| poly.iter().rev().fold(0, |acc, &c| mul(acc, x) ^ c);
+    let positions: Vec<usize> = (0..n)
+        .filter(|&i| evaluate(&locator, alpha_pow(255 - (n - 1 - i) % 255)) == 0)
+        .collect();
+    if positions.len() != errors {
+        return None;
+    }
+
+    // Forney computes the error values from the evaluator
+    let mut evaluator = vec![0u8; parity];
+    for (i, &s) in syndromes.iter().enumerate() {
+        for (j, &l) in locator.iter().enumerate().take(parity - i) {
+            evaluator[i + j] ^= mul(s, l);
+        }
+    }
+    let derivative: Vec<u8> = locator
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(i, &l)| if i % 2 == 1 { l } else { 0 })
+        .collect();
+    for &i in &positions {
+        let x = alpha_pow(n - 1 - i);
+        let x_inverse = div(1, x);
+        let denominator = evaluate(&derivative, x_inverse);
+        if denominator == 0 {
+            return None;
+        }
+        codeword[i] ^= mul(x, div(evaluate(&evaluator, x_inverse), denominator));
+    }
+
+    // Too many errors can also lead to another codeword's neighbourhood
+    let fixed = syndromes_of(codeword, parity).iter().all(|&s| s == 0);
+    fixed.then_some(errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data() -> Vec<u8> {
+        (0..2000u32).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for (data, strength) in [(data(), 8), (b"hi".to_vec(), 1), (Vec::new(), 127)] {
+            let protected = protect(&data, strength).unwrap();
+            assert_eq!(recover(&protected).unwrap(), (data, 0));
+        }
+        assert!(protect(b"hi", 0).is_err());
+        assert!(protect(b"hi", 128).is_err());
+    }
+
+    #[test]
+    fn test_corrects_scattered_errors() {
+        let data = data();
+        let mut protected = protect(&data, 8).unwrap();
+        let blocks = (protected.len() - HEADER_LEN).div_ceil(BLOCK_LEN);
+        let block_len = (protected.len() - HEADER_LEN) / blocks;
+        // 8 errors in every block, parity included
+        for b in 0..blocks {
+            for e in 0..8 {
+                let i = (e * 31 + b * 7) % block_len;
+                protected[HEADER_LEN + i * blocks + b] ^= 0x5a;
+            }
+        }
+        assert_eq!(recover(&protected).unwrap(), (data, 8 * blocks));
+    }
+
+    #[test]
+    fn test_corrects_bursts() {
+        let data = data();
+        let mut protected = protect(&data, 4).unwrap();
+        let blocks = (protected.len() - HEADER_LEN).div_ceil(BLOCK_LEN);
+        for byte in &mut protected[100..100 + 4 * blocks] {
+            *byte = !*byte;
+        }
+        assert_eq!(recover(&protected).unwrap(), (data, 4 * blocks));
+    }
+
+    #[test]
+    fn test_corrects_damaged_header() {
+        let mut protected = protect(b"Hello, friend!", 2).unwrap();
+        protected[0] = b'X';
+        protected[HEADER_COPY_LEN + 5] = 99;
+        protected[HEADER_LEN] ^= 1;
+        assert!(is_protected(&protected));
+        assert_eq!(
+            recover(&protected).unwrap(),
+            (b"Hello, friend!".to_vec(), 1)
+        );
+
+        protected[2 * HEADER_COPY_LEN] = b'Y';
+        assert!(!is_protected(&protected));
+    }
+
+    #[test]
+    fn test_too_many_errors() {
+        let mut protected = protect(b"Hello, friend!", 2).unwrap();
+        for byte in &mut protected[HEADER_LEN..HEADER_LEN + 5] {
+            *byte ^= 1;
+        }
+        assert!(matches!(
+            recover(&protected),
+            Err(Error::ErrorCorrection(_))
+        ));
+    }
+}
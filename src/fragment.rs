@@ -0,0 +1,241 @@
+use std::collections::BTreeMap;
+
+use sha2::{Digest, Sha256};
+
+use crate::Error;
+
+// Each fragment is stored in a chunk of its own:
+//
+//   magic(4) version(1) index(4) total(4) digest(32) data(..)
+//
+// The digest is the SHA-256 of the whole payload. It ties the fragments of
+// one payload together and proves the reassembled payload is complete.
+pub const MAGIC: [u8; 4] = *b"PMEf";
+pub const VERSION: u8 = 1;
+
+pub const DIGEST_LEN: usize = 32;
+const HEADER_LEN: usize = MAGIC.len() + 1 + 4 + 4 + DIGEST_LEN;
+
+/// One piece of a payload split across several chunks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fragment {
+    /// Position of the fragment, from 0.
+    pub index: u32,
+    /// Number of fragments the payload was split into.
+    pub total: u32,
+    pub digest: [u8; DIGEST_LEN],
+    pub data: Vec<u8>,
+}
+
+impl TryFrom<&[u8]> for Fragment {
+    type Error = crate::Error;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        if !is_fragment(value) {
+            return Err(Error::InvalidFragments("not a fragment".to_string()));
+        }
+        if value.len() < HEADER_LEN {
+            return Err(Error::InvalidFragments("truncated header".to_string()));
+        }
+        if value[MAGIC.len()] != VERSION {
+            return Err(Error::InvalidFragments(format!(
+                "unsupported version {}",
+                value[MAGIC.len()]
+            )));
+        }
+
+        let u32_at = |pos: usize| u32::from_be_bytes(value[pos..pos + 4].try_into().unwrap());
+        let fragment = Fragment {
+            index: u32_at(MAGIC.len() + 1),
+            total: u32_at(MAGIC.len() + 5),
+            digest: value[MAGIC.len() + 9..HEADER_LEN].try_into().unwrap(),
+            data: value[HEADER_LEN..].to_vec(),
+        };
+        if fragment.index >= fragment.total {
+            return Err(Error::InvalidFragments(format!(
+                "fragment {} of {} is out of range",
+                fragment.index + 1,
+                fragment.total
+            )));
+        }
+        Ok(fragment)
+    }
+}
+
+impl Fragment {
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.data.len());
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&self.index.to_be_bytes());
+        bytes.extend_from_slice(&self.total.to_be_bytes());
+        bytes.extend_from_slice(&self.digest);
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+}
+
+/// Returns true if `data` looks like a fragment.
+pub fn is_fragment(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// Splits `payload` into `count` fragments of nearly equal size.
+pub fn split(payload: &[u8], count: usize) -> crate::Result<Vec<Fragment>> {
+    if count == 0 || count > payload.len() || count > u32::MAX as usize {
+        return Err(Error::InvalidFragments(format!(
+            "cannot split {} bytes into {} fragments",
+            payload.len(),
+            count
+        )));
+    }
+
+    let digest: [u8; DIGEST_LEN] = Sha256::digest(payload).into();
+    let size = payload.len().div_ceil(count);
+    let (mut fragments, mut start) = (Vec::with_capacity(count), 0);
+    for index in 0..count {
+        // Spread the remainder so no fragment ends up empty
+        let end = (start + size).min(payload.len() - (count - index - 1));
+        fragments.push(Fragment {
+            index: index as u32,
+            total: count as u32,
+            digest,
+            data: payload[start..end].to_vec(),
+        });
+        start = end;
+    }
+    Ok(fragments)
+}
+
+// How many missing fragments an error names
+const MISSING_LISTED: usize = 5;
+
+/// Puts the fragments of one payload back together, whatever order they
+/// come in, checking that none is missing, repeated or altered.
+pub fn reassemble(fragments: impl IntoIterator<Item = Fragment>) -> crate::Result<Vec<u8>> {
+    let mut fragments = fragments.into_iter();
+    let first = fragments
+        .next()
+        .ok_or_else(|| Error::InvalidFragments("no fragments".to_string()))?;
+    let (total, digest) = (first.total, first.digest);
+
+    let mut by_index = BTreeMap::new();
+    by_index.insert(first.index, first.data);
+    for fragment in fragments {
+        if fragment.digest != digest || fragment.total != total {
+            return Err(Error::InvalidFragments(format!(
+                "fragment {} belongs to another payload",
+                fragment.index + 1
+            )));
+        }
+        if by_index.insert(fragment.index, fragment.data).is_some() {
+            return Err(Error::InvalidFragments(format!(
+                "fragment {} of {} appears more than once",
+                fragment.index + 1,
+                total
+            )));
+        }
+    }
+
+    // The total comes from the fragments themselves, so only the first few
+    // missing ones are listed however large it claims to be
+    let missing_count = (total as usize).saturating_sub(by_index.len());
+    if missing_count > 0 {
+        let mut missing: Vec<String> = (0..total)
+            .filter(|index| !by_index.contains_key(index))
+            .take(MISSING_LISTED)
+            .map(|index| (index + 1).to_string())
+            .collect();
+        if missing_count > MISSING_LISTED {
+            missing.push(format!("{} more", missing_count - MISSING_LISTED));
+        }
+        return Err(Error::InvalidFragments(format!(
+            "missing fragment(s) {} of {}",
+            missing.join(", "),
+            total
+        )));
+    }
+
+    let payload: Vec<u8> = by_index.into_values().flatten().collect();
+    if Sha256::digest(&payload).as_slice() != digest {
+        return Err(Error::InvalidFragments(
+            "the reassembled payload does not match its digest".to_string(),
+        ));
+    }
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload() -> Vec<u8> {
+        (0..1000u32).map(|i| (i * 31 % 256) as u8).collect()
+    }
+
+    #[test]
+    fn test_split_and_reassemble() {
+        let payload = payload();
+        for count in [1, 2, 7, 1000] {
+            let fragments = split(&payload, count).unwrap();
+            assert_eq!(fragments.len(), count);
+            assert!(fragments.iter().all(|f| !f.data.is_empty()));
+
+            let parsed = fragments
+                .iter()
+                .rev()
+                .map(|f| Fragment::try_from(f.as_bytes().as_slice()).unwrap());
+            assert_eq!(reassemble(parsed).unwrap(), payload);
+        }
+        assert!(split(&payload, 0).is_err());
+        assert!(split(&payload, 1001).is_err());
+    }
+
+    #[test]
+    fn test_missing_fragments() {
+        let mut fragments = split(&payload(), 5).unwrap();
+        fragments.remove(3);
+        fragments.remove(1);
+
+        let err = reassemble(fragments).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid fragments: missing fragment(s) 2, 4 of 5."
+        );
+    }
+
+    #[test]
+    fn test_missing_fragments_are_summarized() {
+        let mut fragment = split(&payload(), 1).unwrap().remove(0);
+        fragment.index = 2;
+        fragment.total = u32::MAX;
+
+        let err = reassemble([fragment]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid fragments: missing fragment(s) 1, 2, 4, 5, 6, 4294967289 more of 4294967295."
+        );
+    }
+
+    #[test]
+    fn test_duplicated_fragments() {
+        let mut fragments = split(&payload(), 5).unwrap();
+        fragments.push(fragments[2].clone());
+
+        assert!(matches!(
+            reassemble(fragments),
+            Err(Error::InvalidFragments(reason)) if reason.contains("more than once")
+        ));
+    }
+
+    #[test]
+    fn test_altered_or_foreign_fragments() {
+        let mut fragments = split(&payload(), 3).unwrap();
+        fragments[1].data[0] ^= 1;
+        assert!(reassemble(fragments).is_err());
+
+        let mut fragments = split(&payload(), 3).unwrap();
+        fragments[2] = split(b"another payload", 3).unwrap().remove(2);
+        assert!(reassemble(fragments).is_err());
+    }
+}
@@ -0,0 +1,265 @@
+use std::fmt::Display;
+
+use crate::{
+    Error,
+    chunk::{Chunk, ChunkRef},
+};
+
+/// How pixels are stored, as declared in the IHDR chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorType {
+    Grayscale,
+    Rgb,
+    Indexed,
+    GrayscaleAlpha,
+    Rgba,
+}
+
+impl TryFrom<u8> for ColorType {
+    type Error = crate::Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ColorType::Grayscale),
+            2 => Ok(ColorType::Rgb),
+            3 => Ok(ColorType::Indexed),
+            4 => Ok(ColorType::GrayscaleAlpha),
+            6 => Ok(ColorType::Rgba),
+            other => Err(Error::InvalidIhdr(format!("unknown color type {}", other))),
+        }
+    }
+}
+
+impl Display for ColorType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ColorType::Grayscale => "grayscale",
+            ColorType::Rgb => "RGB",
+            ColorType::Indexed => "indexed",
+            ColorType::GrayscaleAlpha => "grayscale + alpha",
+            ColorType::Rgba => "RGBA",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl ColorType {
+    /// Value of the color type byte in IHDR.
+    pub fn value(&self) -> u8 {
+        match self {
+            ColorType::Grayscale => 0,
+            ColorType::Rgb => 2,
+            ColorType::Indexed => 3,
+            ColorType::GrayscaleAlpha => 4,
+            ColorType::Rgba => 6,
+        }
+    }
+
+    /// Number of samples per pixel.
+    pub fn channels(&self) -> u8 {
+        match self {
+            ColorType::Grayscale | ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+
+    /// Bit depths the PNG specification allows for this color type.
+    pub fn allowed_bit_depths(&self) -> &'static [u8] {
+        match self {
+            ColorType::Grayscale => &[1, 2, 4, 8, 16],
+            ColorType::Indexed => &[1, 2, 4, 8],
+            ColorType::Rgb | ColorType::GrayscaleAlpha | ColorType::Rgba => &[8, 16],
+        }
+    }
+}
+
+/// The image header, the first chunk of every PNG.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ihdr {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: ColorType,
+    pub compression_method: u8,
+    pub filter_method: u8,
+    pub interlace_method: u8,
+}
+
+impl TryFrom<&[u8]> for Ihdr {
+    type Error = crate::Error;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        if value.len() != Ihdr::LENGTH {
+            return Err(Error::InvalidIhdr(format!(
+                "expected {} bytes, found {}",
+                Ihdr::LENGTH,
+                value.len()
+            )));
+        }
+
+        let ihdr = Ihdr {
+            width: u32::from_be_bytes(value[0..4].try_into().unwrap()),
+            height: u32::from_be_bytes(value[4..8].try_into().unwrap()),
+            bit_depth: value[8],
+            color_type: ColorType::try_from(value[9])?,
+            compression_method: value[10],
+            filter_method: value[11],
+            interlace_method: value[12],
+        };
+        ihdr.validate()?;
+        Ok(ihdr)
+    }
+}
+
+impl TryFrom<&Chunk> for Ihdr {
+    type Error = crate::Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        Ihdr::try_from(ChunkRef::from(chunk))
+    }
+}
+
+impl TryFrom<ChunkRef<'_>> for Ihdr {
+    type Error = crate::Error;
+
+    fn try_from(chunk: ChunkRef<'_>) -> Result<Self, Self::Error> {
+        if chunk.chunk_type().to_string() != Ihdr::CHUNK_TYPE {
+            return Err(Error::InvalidIhdr(format!(
+                "expected an IHDR chunk, found {}",
+                chunk.chunk_type()
+            )));
+        }
+        Ihdr::try_from(chunk.data())
+    }
+}
+
+impl Display for Ihdr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}x{}, {}-bit {}, {}",
+            self.width,
+            self.height,
+            self.bit_depth,
+            self.color_type,
+            if self.is_interlaced() {
+                "Adam7 interlaced"
+            } else {
+                "non-interlaced"
+            }
+        )
+    }
+}
+
+impl Ihdr {
+    pub const CHUNK_TYPE: &'static str = "IHDR";
+    pub const LENGTH: usize = 13;
+
+    /// Checks the header against the rules of the PNG specification.
+    pub fn validate(&self) -> crate::Result<()> {
+        if self.width == 0 || self.width > i32::MAX as u32 {
+            return Err(Error::InvalidIhdr(format!("invalid width {}", self.width)));
+        }
+        if self.height == 0 || self.height > i32::MAX as u32 {
+            return Err(Error::InvalidIhdr(format!(
+                "invalid height {}",
+                self.height
+            )));
+        }
+        if !self
+            .color_type
+            .allowed_bit_depths()
+            .contains(&self.bit_depth)
+        {
+            return Err(Error::InvalidIhdr(format!(
+                "bit depth {} is not allowed for {} images",
+                self.bit_depth, self.color_type
+            )));
+        }
+        if self.compression_method != 0 {
+            return Err(Error::InvalidIhdr(format!(
+                "unknown compression method {}",
+                self.compression_method
+            )));
+        }
+        if self.filter_method != 0 {
+            return Err(Error::InvalidIhdr(format!(
+                "unknown filter method {}",
+                self.filter_method
+            )));
+        }
+        if self.interlace_method > 1 {
+            return Err(Error::InvalidIhdr(format!(
+                "unknown interlace method {}",
+                self.interlace_method
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn is_interlaced(&self) -> bool {
+        self.interlace_method == 1
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Ihdr::LENGTH);
+        bytes.extend_from_slice(&self.width.to_be_bytes());
+        bytes.extend_from_slice(&self.height.to_be_bytes());
+        bytes.push(self.bit_depth);
+        bytes.push(self.color_type.value());
+        bytes.push(self.compression_method);
+        bytes.push(self.filter_method);
+        bytes.push(self.interlace_method);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ihdr_bytes(bit_depth: u8, color_type: u8, interlace: u8) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&50u32.to_be_bytes());
+        bytes.extend_from_slice(&40u32.to_be_bytes());
+        bytes.extend_from_slice(&[bit_depth, color_type, 0, 0, interlace]);
+        bytes
+    }
+
+    #[test]
+    fn test_parse_ihdr() {
+        let ihdr = Ihdr::try_from(ihdr_bytes(8, 6, 0).as_slice()).unwrap();
+
+        assert_eq!(ihdr.width, 50);
+        assert_eq!(ihdr.height, 40);
+        assert_eq!(ihdr.bit_depth, 8);
+        assert_eq!(ihdr.color_type, ColorType::Rgba);
+        assert!(!ihdr.is_interlaced());
+        assert_eq!(ihdr.as_bytes(), ihdr_bytes(8, 6, 0));
+    }
+
+    #[test]
+    fn test_ihdr_display() {
+        let ihdr = Ihdr::try_from(ihdr_bytes(16, 0, 1).as_slice()).unwrap();
+        assert_eq!(
+            ihdr.to_string(),
+            "50x40, 16-bit grayscale, Adam7 interlaced"
+        );
+    }
+
+    #[test]
+    fn test_illegal_bit_depth() {
+        assert!(Ihdr::try_from(ihdr_bytes(16, 3, 0).as_slice()).is_err());
+        assert!(Ihdr::try_from(ihdr_bytes(4, 2, 0).as_slice()).is_err());
+        assert!(Ihdr::try_from(ihdr_bytes(1, 0, 0).as_slice()).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_ihdr_fields() {
+        assert!(Ihdr::try_from(ihdr_bytes(8, 5, 0).as_slice()).is_err());
+        assert!(Ihdr::try_from(ihdr_bytes(8, 2, 2).as_slice()).is_err());
+        assert!(Ihdr::try_from(&ihdr_bytes(8, 2, 0)[..12]).is_err());
+    }
+}
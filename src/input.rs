@@ -0,0 +1,110 @@
+use std::{fs::File, ops::Deref, path::Path};
+
+use memmap2::Mmap;
+
+/// The bytes of a file opened only to be read. [`Input::open`] memory-maps
+/// regular files, so the operating system pages in what is looked at instead
+/// of the whole file being copied onto the heap, while [`Input::read`] always
+/// copies the file into memory.
+///
+/// Pair it with [`PngRef`](crate::PngRef) to read a file without copying its
+/// chunks either.
+pub enum Input {
+    Mapped(Mmap),
+    Read(Vec<u8>),
+}
+
+impl Input {
+    /// Memory-maps a regular file. Anything else, such as a pipe, or a file
+    /// on a filesystem that cannot be mapped, is read into memory.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be written to or truncated, by this process or any
+    /// other, while the returned `Input` is alive. If it is truncated, reading
+    /// the pages past the new end kills the program with `SIGBUS`; if it is
+    /// written to, the bytes behind the `&[u8]` change while borrowed, which
+    /// is undefined behaviour. Use [`Input::read`] when that cannot be ruled
+    /// out.
+    pub unsafe fn open<P: AsRef<Path>>(path: P) -> crate::Result<Input> {
+        let mut file = File::open(path)?;
+        if !file.metadata()?.is_file() {
+            return Ok(Input::read_all(&mut file)?);
+        }
+
+        // Safety: the caller guarantees the file is left alone while mapped
+        match unsafe { Mmap::map(&file) } {
+            Ok(map) => Ok(Input::Mapped(map)),
+            // Some filesystems cannot be mapped
+            Err(_) => Ok(Input::read_all(&mut file)?),
+        }
+    }
+
+    /// Reads a whole file into memory, which stays valid whatever happens to
+    /// the file afterwards.
+    pub fn read<P: AsRef<Path>>(path: P) -> crate::Result<Input> {
+        Ok(Input::read_all(&mut File::open(path)?)?)
+    }
+
+    fn read_all(file: &mut File) -> std::io::Result<Input> {
+        let mut bytes = Vec::new();
+        std::io::Read::read_to_end(file, &mut bytes)?;
+        Ok(Input::Read(bytes))
+    }
+
+    /// Whether the file is memory-mapped rather than read into memory.
+    pub fn is_mapped(&self) -> bool {
+        matches!(self, Input::Mapped(_))
+    }
+}
+
+impl Deref for Input {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Input::Mapped(map) => map,
+            Input::Read(bytes) => bytes,
+        }
+    }
+}
+
+impl AsRef<[u8]> for Input {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::PNG_FILE;
+
+    #[test]
+    fn test_maps_regular_files() {
+        let path = std::env::temp_dir().join(format!("pngme-input-{}.png", std::process::id()));
+        std::fs::write(&path, PNG_FILE).unwrap();
+        let input = unsafe { Input::open(&path) };
+        let read = Input::read(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let input = input.unwrap();
+        assert!(input.is_mapped());
+        assert_eq!(&*input, PNG_FILE);
+
+        let read = read.unwrap();
+        assert!(!read.is_mapped());
+        assert_eq!(&*read, PNG_FILE);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_reads_other_files() {
+        let input = unsafe { Input::open("/dev/null") }.unwrap();
+        assert!(!input.is_mapped());
+        assert!(input.is_empty());
+
+        assert!(unsafe { Input::open("/no/such/file.png") }.is_err());
+        assert!(Input::read("/no/such/file.png").is_err());
+    }
+}
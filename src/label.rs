@@ -0,0 +1,160 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::Error;
+
+// Labeled messages start with this header, outside of any encryption so
+// they can be listed without a key:
+//
+//   magic(4) version(1) label length(1) label(..) created(8) message(..)
+//
+// The creation time is in seconds since the Unix epoch.
+pub const MAGIC: [u8; 4] = *b"PMEl";
+pub const VERSION: u8 = 1;
+
+const MAX_LABEL_LEN: usize = 255;
+
+/// The name and creation time of a message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub name: String,
+    /// Seconds since the Unix epoch.
+    pub created: u64,
+}
+
+impl Label {
+    /// A label created now, after checking the name.
+    pub fn new(name: &str) -> crate::Result<Label> {
+        validate_label(name)?;
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        Ok(Label {
+            name: name.to_string(),
+            created,
+        })
+    }
+}
+
+/// Checks that a label is 1 to 255 bytes of UTF-8 without control characters.
+pub fn validate_label(name: &str) -> crate::Result<()> {
+    if name.is_empty() || name.len() > MAX_LABEL_LEN {
+        return Err(Error::InvalidLabel(format!(
+            "{:?} must be 1 to {} bytes long",
+            name, MAX_LABEL_LEN
+        )));
+    }
+    if name.chars().any(char::is_control) {
+        return Err(Error::InvalidLabel(format!(
+            "{:?} cannot contain control characters",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// Returns true if `data` starts with a label.
+pub fn is_labeled(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// Puts `label` in front of a message.
+pub fn wrap(label: &Label, message: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(MAGIC.len() + 2 + label.name.len() + 8 + message.len());
+    data.extend_from_slice(&MAGIC);
+    data.push(VERSION);
+    data.push(label.name.len() as u8);
+    data.extend_from_slice(label.name.as_bytes());
+    data.extend_from_slice(&label.created.to_be_bytes());
+    data.extend_from_slice(message);
+    data
+}
+
+/// Splits the label off a message, returning the data untouched if it has
+/// none.
+pub fn unwrap(data: &[u8]) -> crate::Result<(Option<Label>, &[u8])> {
+    if !is_labeled(data) {
+        return Ok((None, data));
+    }
+    let truncated = || Error::InvalidLabel("truncated header".to_string());
+
+    let rest = &data[MAGIC.len()..];
+    let (&version, rest) = rest.split_first().ok_or_else(truncated)?;
+    if version != VERSION {
+        return Err(Error::InvalidLabel(format!(
+            "unsupported version {}",
+            version
+        )));
+    }
+    let (&length, rest) = rest.split_first().ok_or_else(truncated)?;
+    let (name, rest) = rest
+        .split_at_checked(length as usize)
+        .ok_or_else(truncated)?;
+    let (created, message) = rest.split_at_checked(8).ok_or_else(truncated)?;
+
+    let label = Label {
+        name: String::from_utf8(name.to_vec())?,
+        created: u64::from_be_bytes(created.try_into().unwrap()),
+    };
+    Ok((Some(label), message))
+}
+
+/// Formats seconds since the Unix epoch as a UTC date and time.
+pub fn format_timestamp(secs: u64) -> String {
+    let (days, secs_of_day) = ((secs / 86400) as i64, secs % 86400);
+
+    // Howard Hinnant's days-to-civil algorithm
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_and_unwrap() {
+        let label = Label::new("holiday plans").unwrap();
+        let data = wrap(&label, b"PMEc sealed bytes");
+
+        let (unwrapped, message) = unwrap(&data).unwrap();
+        assert_eq!(unwrapped, Some(label));
+        assert_eq!(message, b"PMEc sealed bytes");
+
+        assert_eq!(unwrap(b"plain").unwrap(), (None, &b"plain"[..]));
+        assert!(unwrap(&data[..10]).is_err());
+    }
+
+    #[test]
+    fn test_validate_label() {
+        assert!(validate_label("notes").is_ok());
+        assert!(validate_label("r\u{e9}sum\u{e9} 2024").is_ok());
+        assert!(validate_label("").is_err());
+        assert!(validate_label(&"x".repeat(256)).is_err());
+        assert!(validate_label("line\nbreak").is_err());
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_timestamp(951782400), "2000-02-29 00:00:00 UTC");
+        assert_eq!(format_timestamp(1700000000), "2023-11-14 22:13:20 UTC");
+    }
+}
@@ -0,0 +1,40 @@
+//! Encode, decode and inspect hidden messages in PNG files.
+//!
+//! The library exposes the PNG building blocks ([`Png`], [`Chunk`] and
+//! [`ChunkType`]) along with the high level operations in [`commands`] that
+//! the `pngme` binary is built on. [`ChunkReader`] reads chunks one at a time
+//! from files too large to load, or from pipes, while [`PngRef`] and
+//! [`ChunkRef`] read a file already in memory, or memory-mapped with
+//! [`input::Input`], without copying its chunks.
+
+pub mod analyze;
+pub mod chunk;
+pub mod chunk_type;
+pub mod commands;
+pub mod crypto;
+pub mod encoder;
+pub mod error;
+pub mod fec;
+pub mod fragment;
+pub mod ihdr;
+pub mod input;
+pub mod label;
+pub mod lsb;
+pub mod output;
+pub mod payload;
+pub mod pixels;
+pub mod png;
+pub mod reader;
+pub mod rewrite;
+pub mod signature;
+pub mod text;
+pub mod validate;
+
+pub use chunk::{Chunk, ChunkRef};
+pub use chunk_type::ChunkType;
+pub use error::Error;
+pub use ihdr::Ihdr;
+pub use png::{Png, PngRef};
+pub use reader::ChunkReader;
+
+pub type Result<T> = std::result::Result<T, Error>;
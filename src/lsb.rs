@@ -0,0 +1,612 @@
+use std::{fmt::Display, str::FromStr};
+
+use rand_chacha::{
+    ChaCha20Rng,
+    rand_core::{RngCore, SeedableRng},
+};
+use sha2::{Digest, Sha256};
+
+use crate::{Error, ihdr::ColorType, pixels::Image};
+
+// The payload is preceded by its length as a big-endian u32
+const HEADER_LENGTH: usize = 4;
+
+// Domain separation for the seed of the keyed slot order
+const ORDER_TAG: &[u8] = b"pngme lsb order v1";
+
+/// The colour channels allowed to carry hidden bits, written as a subset of
+/// the letters `rgba`. Grayscale images use their gray channel when any of
+/// red, green or blue is selected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelMask {
+    pub red: bool,
+    pub green: bool,
+    pub blue: bool,
+    pub alpha: bool,
+}
+
+impl Default for ChannelMask {
+    fn default() -> Self {
+        ChannelMask {
+            red: true,
+            green: true,
+            blue: true,
+            alpha: false,
+        }
+    }
+}
+
+impl FromStr for ChannelMask {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut mask = ChannelMask {
+            red: false,
+            green: false,
+            blue: false,
+            alpha: false,
+        };
+        for c in s.chars() {
+            match c.to_ascii_lowercase() {
+                'r' => mask.red = true,
+                'g' => mask.green = true,
+                'b' => mask.blue = true,
+                'a' => mask.alpha = true,
+                other => {
+                    return Err(Error::InvalidLsb(format!(
+                        "unknown channel {:?}, expected r, g, b or a",
+                        other
+                    )));
+                }
+            }
+        }
+        if s.is_empty() {
+            return Err(Error::InvalidLsb("no channel selected".to_string()));
+        }
+        Ok(mask)
+    }
+}
+
+impl Display for ChannelMask {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (selected, letter) in [
+            (self.red, 'r'),
+            (self.green, 'g'),
+            (self.blue, 'b'),
+            (self.alpha, 'a'),
+        ] {
+            if selected {
+                write!(f, "{}", letter)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ChannelMask {
+    // Indices of the selected channels among the samples of a pixel
+    fn select(&self, color_type: ColorType) -> Vec<usize> {
+        let color = self.red || self.green || self.blue;
+        let wanted: &[(bool, usize)] = match color_type {
+            ColorType::Grayscale => &[(color, 0)],
+            ColorType::GrayscaleAlpha => &[(color, 0), (self.alpha, 1)],
+            ColorType::Rgb => &[(self.red, 0), (self.green, 1), (self.blue, 2)],
+            ColorType::Rgba => &[
+                (self.red, 0),
+                (self.green, 1),
+                (self.blue, 2),
+                (self.alpha, 3),
+            ],
+            // The index is the only sample, whatever colour it resolves to
+            ColorType::Indexed => &[(true, 0)],
+        };
+        wanted
+            .iter()
+            .filter(|(selected, _)| *selected)
+            .map(|&(_, channel)| channel)
+            .collect()
+    }
+}
+
+/// Where and how densely hidden bits are stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LsbOptions {
+    /// Low bits of each selected sample that are replaced.
+    pub bits_per_channel: u8,
+    pub channels: ChannelMask,
+    /// Scatter the bits in an order derived from this key, see [`KeyedSlots`].
+    pub key: Option<String>,
+}
+
+impl Default for LsbOptions {
+    fn default() -> Self {
+        LsbOptions {
+            bits_per_channel: 1,
+            channels: ChannelMask::default(),
+            key: None,
+        }
+    }
+}
+
+/// A sample that carries hidden bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slot {
+    pub x: u32,
+    pub y: u32,
+    pub channel: usize,
+}
+
+/// Every sample that can carry hidden bits, in row-major order.
+///
+/// Indexed pixels whose index falls outside the palette are skipped, as are
+/// all pixels of a palette with fewer than two entries.
+pub fn slots<'a>(
+    image: &'a Image,
+    options: &LsbOptions,
+) -> crate::Result<impl Iterator<Item = Slot> + 'a> {
+    check_options(image, options)?;
+
+    let channels = options.channels.select(image.color_type());
+    let width = image.width() as usize;
+    let count = channels.len();
+    let total = width * image.height() as usize * count;
+    let palette_size = match image.color_type() {
+        ColorType::Indexed => image.palette().map_or(0, |p| p.len()),
+        _ => 0,
+    };
+
+    Ok((0..total)
+        .map(move |i| Slot {
+            x: (i / count % width) as u32,
+            y: (i / count / width) as u32,
+            channel: channels[i % count],
+        })
+        .filter(move |slot| {
+            image.color_type() != ColorType::Indexed
+                || (palette_size >= 2
+                    && (image.sample(slot.x, slot.y, slot.channel) as usize) < palette_size)
+        }))
+}
+
+/// The [`slots`] of an image in an order that only holders of the key can
+/// reproduce.
+///
+/// The order is a Fisher-Yates shuffle driven by ChaCha20, seeded with a hash
+/// of the key. Every slot of the image is listed up front, which takes
+/// memory in proportion to its size, but the shuffle only makes one swap per
+/// item taken, so reading the first few slots costs little more than that.
+pub struct KeyedSlots {
+    slots: Vec<Slot>,
+    next: usize,
+    rng: ChaCha20Rng,
+}
+
+impl KeyedSlots {
+    pub fn new(image: &Image, options: &LsbOptions, key: &[u8]) -> crate::Result<KeyedSlots> {
+        let mut hasher = Sha256::new();
+        hasher.update(ORDER_TAG);
+        hasher.update(key);
+
+        Ok(KeyedSlots {
+            slots: slots(image, options)?.collect(),
+            next: 0,
+            rng: ChaCha20Rng::from_seed(hasher.finalize().into()),
+        })
+    }
+
+    // Uniform in 0..bound, rejecting the values that would bias the modulo
+    fn below(&mut self, bound: usize) -> usize {
+        let bound = bound as u64;
+        let zone = u64::MAX - u64::MAX % bound;
+        loop {
+            let value = self.rng.next_u64();
+            if value < zone {
+                return (value % bound) as usize;
+            }
+        }
+    }
+}
+
+impl Iterator for KeyedSlots {
+    type Item = Slot;
+
+    fn next(&mut self) -> Option<Slot> {
+        let remaining = self.slots.len() - self.next;
+        if remaining == 0 {
+            return None;
+        }
+        let chosen = self.next + self.below(remaining);
+        self.slots.swap(self.next, chosen);
+        self.next += 1;
+        Some(self.slots[self.next - 1])
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.slots.len() - self.next;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for KeyedSlots {}
+
+/// Number of payload bytes the image can hold with these options.
+pub fn capacity(image: &Image, options: &LsbOptions) -> crate::Result<usize> {
+    let bits = slots(image, options)?.count() * options.bits_per_channel as usize;
+    Ok((bits / 8).saturating_sub(HEADER_LENGTH))
+}
+
+/// Hides `payload` in the low bits of the image samples, in row-major order
+/// or in the order given by the key if there is one.
+pub fn embed(image: &mut Image, payload: &[u8], options: &LsbOptions) -> crate::Result<()> {
+    let positions: Vec<Slot> = match &options.key {
+        Some(key) => KeyedSlots::new(image, options, key.as_bytes())?.collect(),
+        None => slots(image, options)?.collect(),
+    };
+    embed_at(image, positions, payload, options)
+}
+
+/// Reads a payload hidden with [`embed`] with the same options.
+pub fn extract(image: &Image, options: &LsbOptions) -> crate::Result<Vec<u8>> {
+    match &options.key {
+        Some(key) => extract_from(
+            image,
+            KeyedSlots::new(image, options, key.as_bytes())?,
+            options,
+        ),
+        None => extract_from(image, slots(image, options)?, options),
+    }
+}
+
+// Writes the length header and payload into the given samples, in order
+fn embed_at(
+    image: &mut Image,
+    positions: Vec<Slot>,
+    payload: &[u8],
+    options: &LsbOptions,
+) -> crate::Result<()> {
+    let bits_per_channel = options.bits_per_channel as usize;
+    let capacity = (positions.len() * bits_per_channel / 8).saturating_sub(HEADER_LENGTH);
+    if payload.len() > capacity || payload.len() > u32::MAX as usize {
+        return Err(Error::PayloadTooLarge {
+            size: payload.len(),
+            capacity,
+        });
+    }
+
+    let mut data = (payload.len() as u32).to_be_bytes().to_vec();
+    data.extend_from_slice(payload);
+    let mut bits = data
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1));
+
+    let order = PaletteOrder::new(image);
+    for slot in positions
+        .into_iter()
+        .take((data.len() * 8).div_ceil(bits_per_channel))
+    {
+        let value = (0..bits_per_channel).fold(0u16, |acc, _| {
+            (acc << 1) | bits.next().unwrap_or_default() as u16
+        });
+        let sample = image.sample(slot.x, slot.y, slot.channel);
+        let sample = match &order {
+            Some(order) => order.with_parity(sample, value),
+            None => {
+                let mask = (1u16 << bits_per_channel) - 1;
+                (sample & !mask) | value
+            }
+        };
+        image.set_sample(slot.x, slot.y, slot.channel, sample);
+    }
+
+    Ok(())
+}
+
+// Reads the length header and payload back from the given samples
+fn extract_from(
+    image: &Image,
+    positions: impl Iterator<Item = Slot>,
+    options: &LsbOptions,
+) -> crate::Result<Vec<u8>> {
+    let bits_per_channel = options.bits_per_channel as usize;
+    let order = PaletteOrder::new(image);
+    let mut bits = positions.flat_map(|slot| {
+        let sample = image.sample(slot.x, slot.y, slot.channel);
+        let value = match &order {
+            Some(order) => order.parity(sample),
+            None => sample,
+        };
+        (0..bits_per_channel)
+            .rev()
+            .map(move |i| (value >> i) as u8 & 1)
+    });
+    let mut read_bytes = |count: usize| -> Option<Vec<u8>> {
+        (0..count)
+            .map(|_| (0..8).try_fold(0u8, |acc, _| Some((acc << 1) | bits.next()?)))
+            .collect()
+    };
+
+    let not_found = || Error::InvalidLsb("no hidden message found".to_string());
+    let header = read_bytes(HEADER_LENGTH).ok_or_else(not_found)?;
+    let length = u32::from_be_bytes(header.try_into().unwrap()) as usize;
+    if length > capacity(image, options)? {
+        return Err(not_found());
+    }
+    read_bytes(length).ok_or_else(not_found)
+}
+
+fn check_options(image: &Image, options: &LsbOptions) -> crate::Result<()> {
+    let max_bits = match image.color_type() {
+        // Changing more than one bit of an index can pick any colour
+        ColorType::Indexed => 1,
+        _ => image.bit_depth().min(8),
+    };
+    if options.bits_per_channel == 0 || options.bits_per_channel > max_bits {
+        return Err(Error::InvalidLsb(format!(
+            "{} bits per channel is not possible for a {}-bit {} image, use 1 to {}",
+            options.bits_per_channel,
+            image.bit_depth(),
+            image.color_type(),
+            max_bits
+        )));
+    }
+    Ok(())
+}
+
+// Palette entries sorted by luminance. Flipping the low bit of an index could
+// select an unrelated colour or one past the end of the palette, so indexed
+// pixels hide their bit in the parity of their rank instead and only ever
+// move to a neighbouring rank, which has a similar brightness.
+struct PaletteOrder {
+    rank: Vec<usize>,
+    by_rank: Vec<u16>,
+}
+
+impl PaletteOrder {
+    fn new(image: &Image) -> Option<PaletteOrder> {
+        if image.color_type() != ColorType::Indexed {
+            return None;
+        }
+        let palette = image.palette()?;
+        let luminance = |[r, g, b]: [u8; 3]| 299 * r as u32 + 587 * g as u32 + 114 * b as u32;
+
+        let mut by_rank: Vec<u16> = (0..palette.len() as u16).collect();
+        by_rank.sort_by_key(|&i| (luminance(palette[i as usize]), i));
+        let mut rank = vec![0; palette.len()];
+        for (r, &i) in by_rank.iter().enumerate() {
+            rank[i as usize] = r;
+        }
+        Some(PaletteOrder { rank, by_rank })
+    }
+
+    fn parity(&self, index: u16) -> u16 {
+        (self.rank[index as usize] & 1) as u16
+    }
+
+    fn with_parity(&self, index: u16, bit: u16) -> u16 {
+        let rank = self.rank[index as usize];
+        if (rank & 1) as u16 == bit {
+            return index;
+        }
+        // The last entry of an odd-sized palette has no partner above it
+        let neighbour = if rank ^ 1 < self.by_rank.len() {
+            rank ^ 1
+        } else {
+            rank - 1
+        };
+        self.by_rank[neighbour]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{encoder::EncoderSettings, pixels::Samples, png::Png, png::tests::PNG_FILE};
+
+    fn image(color_type: ColorType, bit_depth: u8, palette_size: usize) -> Image {
+        let (width, height) = (40u32, 30u32);
+        let count = (width * height) as usize * color_type.channels() as usize;
+        let max = match color_type {
+            ColorType::Indexed => palette_size as u32 - 1,
+            _ => (1u32 << bit_depth) - 1,
+        };
+        let values = (0..count).map(|i| (i as u32 * 7919 % (max + 1)) as u16);
+        let samples = match bit_depth {
+            16 => Samples::U16(values.collect()),
+            _ => Samples::U8(values.map(|v| v as u8).collect()),
+        };
+        let palette = (color_type == ColorType::Indexed).then(|| {
+            (0..palette_size)
+                .map(|i| [(i * 53) as u8, (i * 17) as u8, (i * 101) as u8])
+                .collect()
+        });
+        Image::new(width, height, color_type, bit_depth, palette, samples).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip_every_color_type() {
+        let payload = b"The quick brown fox jumps over the lazy dog";
+        for (color_type, bit_depth, bits) in [
+            (ColorType::Grayscale, 1, 1),
+            (ColorType::Grayscale, 4, 2),
+            (ColorType::Grayscale, 16, 8),
+            (ColorType::GrayscaleAlpha, 8, 1),
+            (ColorType::Rgb, 8, 2),
+            (ColorType::Rgb, 16, 3),
+            (ColorType::Rgba, 8, 1),
+            (ColorType::Indexed, 8, 1),
+        ] {
+            let mut image = image(color_type, bit_depth, 200);
+            let options = LsbOptions {
+                bits_per_channel: bits,
+                ..Default::default()
+            };
+            embed(&mut image, payload, &options).unwrap();
+            assert_eq!(
+                extract(&image, &options).unwrap(),
+                payload,
+                "{}-bit {}",
+                bit_depth,
+                color_type
+            );
+        }
+    }
+
+    #[test]
+    fn test_only_low_bits_of_selected_channels_change() {
+        let original = image(ColorType::Rgba, 8, 0);
+        let mut image = original.clone();
+        let options = LsbOptions {
+            bits_per_channel: 2,
+            channels: ChannelMask::from_str("gb").unwrap(),
+            ..Default::default()
+        };
+        embed(&mut image, &[0xAA; 100], &options).unwrap();
+
+        for y in 0..image.height() {
+            for x in 0..image.width() {
+                for c in 0..4 {
+                    let (before, after) = (original.sample(x, y, c), image.sample(x, y, c));
+                    match c {
+                        1 | 2 => assert_eq!(before >> 2, after >> 2),
+                        _ => assert_eq!(before, after),
+                    }
+                }
+            }
+        }
+        assert_eq!(extract(&image, &options).unwrap(), vec![0xAA; 100]);
+    }
+
+    #[test]
+    fn test_palette_indices_stay_in_range() {
+        // An odd palette size exercises the unpaired last entry
+        let original = image(ColorType::Indexed, 4, 15);
+        let mut image = original.clone();
+        let options = LsbOptions::default();
+        let payload: Vec<u8> = (0..capacity(&image, &options).unwrap() as u8).collect();
+        embed(&mut image, &payload, &options).unwrap();
+
+        let order = PaletteOrder::new(&image).unwrap();
+        for y in 0..image.height() {
+            for x in 0..image.width() {
+                let (before, after) = (original.sample(x, y, 0), image.sample(x, y, 0));
+                assert!(after < 15);
+                assert!(order.rank[before as usize].abs_diff(order.rank[after as usize]) <= 1);
+            }
+        }
+        assert_eq!(extract(&image, &options).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let image = image(ColorType::Rgb, 8, 0);
+        let options = LsbOptions {
+            bits_per_channel: 2,
+            ..Default::default()
+        };
+        // 40 x 30 pixels, 3 channels, 2 bits each, minus the header
+        assert_eq!(capacity(&image, &options).unwrap(), 40 * 30 * 3 * 2 / 8 - 4);
+
+        let mut image = image;
+        let too_large = vec![0; 40 * 30 * 3 * 2 / 8 - 3];
+        assert!(matches!(
+            embed(&mut image, &too_large, &options),
+            Err(Error::PayloadTooLarge {
+                size: 897,
+                capacity: 896
+            })
+        ));
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        let gray = image(ColorType::Grayscale, 2, 0);
+        let indexed = image(ColorType::Indexed, 8, 16);
+        let bits = |bits_per_channel| LsbOptions {
+            bits_per_channel,
+            ..Default::default()
+        };
+        assert!(capacity(&gray, &bits(3)).is_err());
+        assert!(capacity(&gray, &bits(0)).is_err());
+        assert!(capacity(&indexed, &bits(2)).is_err());
+        assert!(ChannelMask::from_str("rgx").is_err());
+        assert!(ChannelMask::from_str("").is_err());
+        assert_eq!(ChannelMask::from_str("AgR").unwrap().to_string(), "rga");
+    }
+
+    #[test]
+    fn test_keyed_slots_are_a_permutation() {
+        let image = image(ColorType::Rgb, 8, 0);
+        let options = LsbOptions::default();
+        let sequential: Vec<Slot> = slots(&image, &options).unwrap().collect();
+        let keyed: Vec<Slot> = KeyedSlots::new(&image, &options, b"key").unwrap().collect();
+        let again: Vec<Slot> = KeyedSlots::new(&image, &options, b"key").unwrap().collect();
+        let other: Vec<Slot> = KeyedSlots::new(&image, &options, b"other key")
+            .unwrap()
+            .collect();
+
+        assert_eq!(keyed, again);
+        assert_ne!(keyed, sequential);
+        assert_ne!(keyed, other);
+        let mut sorted = keyed.clone();
+        sorted.sort_by_key(|s| (s.y, s.x, s.channel));
+        assert_eq!(sorted, sequential);
+    }
+
+    #[test]
+    fn test_keyed_round_trip() {
+        let original = image(ColorType::Rgba, 8, 0);
+        let mut image = original.clone();
+        let options = LsbOptions {
+            key: Some("correct horse".to_string()),
+            ..Default::default()
+        };
+        embed(&mut image, b"scattered", &options).unwrap();
+        assert_eq!(extract(&image, &options).unwrap(), b"scattered");
+
+        // The bits are spread over the image rather than packed in the first rows
+        let changed_rows = (0..image.height())
+            .filter(|&y| (0..image.width()).any(|x| image.rgba8(x, y) != original.rgba8(x, y)))
+            .count();
+        assert!(changed_rows > 10);
+
+        let wrong_key = LsbOptions {
+            key: Some("battery staple".to_string()),
+            ..Default::default()
+        };
+        assert_ne!(
+            extract(&image, &wrong_key).ok(),
+            Some(b"scattered".to_vec())
+        );
+        assert_ne!(
+            extract(&image, &LsbOptions::default()).ok(),
+            Some(b"scattered".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_no_hidden_message() {
+        let image = image(ColorType::Rgb, 8, 0);
+        let options = LsbOptions {
+            bits_per_channel: 8,
+            ..Default::default()
+        };
+        assert!(matches!(
+            extract(&image, &options),
+            Err(Error::InvalidLsb(_))
+        ));
+    }
+
+    #[test]
+    fn test_survives_reencoding() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let mut image = png.decode_image().unwrap();
+        let options = LsbOptions::default();
+        embed(&mut image, b"hidden in plain sight", &options).unwrap();
+        png.encode_image(&image, &EncoderSettings::default())
+            .unwrap();
+
+        let png = Png::try_from(png.as_bytes().as_slice()).unwrap();
+        let image = png.decode_image().unwrap();
+        assert_eq!(extract(&image, &options).unwrap(), b"hidden in plain sight");
+    }
+}
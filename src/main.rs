@@ -1,36 +1,254 @@
-use args::{Args, Commands};
-use clap::Parser;
+use std::{
+    fs,
+    io::{self, Read},
+    path::Path,
+    process::ExitCode,
+    str::FromStr,
+};
 
-mod args;
-mod chunk;
-mod chunk_type;
-mod commands;
-mod png;
+use args::{
+    Args, Commands, CompressionMethod, LsbArgs, MessageArgs, Method, OpenArgs, PassphraseArgs,
+    SealArgs, TextCommands,
+};
+use clap::{CommandFactory, Parser, error::ErrorKind};
+use pngme::{
+    commands::{self, DecodeOptions, EncodeOptions, RemoveOptions, TextOptions},
+    crypto,
+    lsb::{ChannelMask, LsbOptions},
+    payload::{self, Compression, Payload},
+    signature,
+};
 
-pub type Error = Box<dyn std::error::Error>;
-pub type Result<T> = std::result::Result<T, Error>;
+mod args;
 
-fn main() -> Result<()> {
+fn main() -> ExitCode {
     let args = Args::parse();
 
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            ExitCode::from(err.exit_code())
+        }
+    }
+}
+
+fn run(args: Args) -> pngme::Result<()> {
     match args.cmd {
         Commands::Encode {
             filepath,
             chunk,
             message,
+            method,
+            lsb,
+            seal,
+            split,
+            label,
         } => {
-            commands::encode(&filepath, &chunk, &message)?;
+            let options = encode_options(seal, split, label)?;
+            let message = read_message(message)?;
+            match (method, chunk) {
+                (Method::Lsb, None) => {
+                    commands::encode_lsb(&filepath, &message, &options, &lsb_options(lsb)?)?
+                }
+                (Method::Lsb, Some(_)) => chunk_with_lsb(
+                    "encode",
+                    "--method lsb does not take a <CHUNK>, give the message with --message",
+                ),
+                (Method::Chunk, chunk) => {
+                    let chunk = chunk.expect("clap requires a chunk");
+                    commands::encode(&filepath, &chunk, &message, &options)?
+                }
+            }
         }
-        Commands::Decode { filepath, chunk } => {
-            commands::decode(&filepath, &chunk)?;
+        Commands::Decode {
+            filepath,
+            chunk,
+            method,
+            lsb,
+            open,
+            label,
+        } => {
+            let options = decode_options(open, label)?;
+            match (method, chunk) {
+                (Method::Lsb, None) => {
+                    commands::decode_lsb(&filepath, &options, &lsb_options(lsb)?)?
+                }
+                (Method::Lsb, Some(_)) => {
+                    chunk_with_lsb("decode", "--method lsb does not take a <CHUNK>")
+                }
+                (Method::Chunk, chunk) => {
+                    let chunk = chunk.expect("clap requires a chunk");
+                    commands::decode(&filepath, &chunk, &options)?
+                }
+            }
         }
-        Commands::Remove { filepath, chunk } => {
-            commands::remove(&filepath, &chunk)?;
+        Commands::Remove {
+            filepath,
+            chunk,
+            label,
+            all,
+            backup,
+        } => {
+            commands::remove(&filepath, &chunk, &RemoveOptions { label, all, backup })?;
+        }
+        Commands::List { filepath } => {
+            commands::list(&filepath)?;
         }
         Commands::Print { filepath } => {
             commands::print(&filepath)?;
         }
+        Commands::Repair { filepath } => {
+            commands::repair(&filepath)?;
+        }
+        Commands::Info { filepath } => {
+            commands::info(&filepath)?;
+        }
+        Commands::Capacity { filepath, lsb } => {
+            commands::capacity(&filepath, &lsb_options(lsb)?)?;
+        }
+        Commands::Text { cmd } => match cmd {
+            TextCommands::List { filepath } => commands::text_list(&filepath)?,
+            TextCommands::Get { filepath, keyword } => commands::text_get(&filepath, &keyword)?,
+            TextCommands::Set {
+                filepath,
+                keyword,
+                value,
+                compress,
+                language,
+                translated_keyword,
+            } => {
+                let options = TextOptions {
+                    compress,
+                    language_tag: language,
+                    translated_keyword,
+                };
+                commands::text_set(&filepath, &keyword, &value, &options)?;
+            }
+            TextCommands::Delete { filepath, keyword } => {
+                commands::text_delete(&filepath, &keyword)?
+            }
+        },
+        Commands::Validate { filepath, json } => {
+            commands::validate(&filepath, json)?;
+        }
+        Commands::Analyze { filepaths, json } => {
+            commands::analyze(&filepaths, json)?;
+        }
+        Commands::Keygen { output, signing } => {
+            commands::keygen(output.as_deref(), signing)?;
+        }
+        Commands::Sign { filepath, key } => {
+            let key = signature::parse_signing_key(&fs::read_to_string(key)?)?;
+            commands::sign(&filepath, &key)?;
+        }
+        Commands::Verify {
+            filepath,
+            public_key,
+        } => {
+            let public_key = match public_key {
+                Some(key) => Some(signature::parse_verifying_key(&key)?),
+                None => None,
+            };
+            commands::verify(&filepath, public_key.as_ref())?;
+        }
     }
 
     Ok(())
 }
+
+// Resolves the passphrase given directly or through a file
+fn read_passphrase(args: PassphraseArgs) -> pngme::Result<Option<String>> {
+    if let Some(path) = args.passphrase_file {
+        let contents = fs::read_to_string(path)?;
+        let passphrase = contents.lines().next().unwrap_or_default();
+        return Ok(Some(passphrase.to_string()));
+    }
+    Ok(args.passphrase)
+}
+
+fn encode_options(
+    args: SealArgs,
+    split: Option<usize>,
+    label: Option<String>,
+) -> pngme::Result<EncodeOptions> {
+    Ok(EncodeOptions {
+        passphrase: read_passphrase(args.passphrase)?,
+        recipients: args
+            .recipients
+            .iter()
+            .map(|r| crypto::parse_public_key(r))
+            .collect::<pngme::Result<_>>()?,
+        compression: match args.compress {
+            None => Compression::None,
+            Some(CompressionMethod::Deflate) => Compression::Deflate,
+            Some(CompressionMethod::Zstd) => Compression::Zstd,
+        },
+        split,
+        label,
+        error_correction: args.ecc,
+        backup: args.backup,
+    })
+}
+
+fn decode_options(args: OpenArgs, label: Option<String>) -> pngme::Result<DecodeOptions> {
+    let identity = match args.identity {
+        Some(path) => Some(crypto::parse_identity(&fs::read_to_string(path)?)?),
+        None => None,
+    };
+    Ok(DecodeOptions {
+        passphrase: read_passphrase(args.passphrase)?,
+        identity,
+        output: args.output,
+        label,
+    })
+}
+
+// Reads what to hide: the message itself, a file or stdin. Clap makes sure
+// exactly one of them is given.
+fn read_message(args: MessageArgs) -> pngme::Result<Payload> {
+    if let Some(path) = args.file {
+        let data = fs::read(&path)?;
+        let filename = Path::new(&path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        return Ok(attachment(data, filename, args.mime_type));
+    }
+    if args.stdin {
+        let mut data = Vec::new();
+        io::stdin().read_to_end(&mut data)?;
+        return Ok(attachment(data, None, args.mime_type));
+    }
+    let message = args.message.or(args.text).unwrap_or_default();
+    Ok(Payload::new(message.into_bytes()))
+}
+
+// Clap can only make a chunk required for a given --method, not refuse it, so
+// a chunk given with --method lsb is turned into a usage error here
+fn chunk_with_lsb(subcommand: &str, message: &str) -> ! {
+    let mut command = Args::command();
+    command.build();
+    command
+        .find_subcommand_mut(subcommand)
+        .expect("unknown subcommand")
+        .error(ErrorKind::ArgumentConflict, message)
+        .exit()
+}
+
+// Wraps file contents with their name and MIME type
+fn attachment(data: Vec<u8>, filename: Option<String>, mime_type: Option<String>) -> Payload {
+    let mime_type = mime_type.unwrap_or_else(|| payload::guess_mime_type(&data).to_string());
+    Payload {
+        data,
+        filename,
+        mime_type: Some(mime_type),
+    }
+}
+
+fn lsb_options(args: LsbArgs) -> pngme::Result<LsbOptions> {
+    Ok(LsbOptions {
+        bits_per_channel: args.bits,
+        channels: ChannelMask::from_str(&args.channels)?,
+        key: args.key,
+    })
+}
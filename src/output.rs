@@ -0,0 +1,287 @@
+use std::{
+    fs::{self, File, FileTimes, Metadata},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use rand_core::{OsRng, RngCore};
+
+/// Suffix of the backup kept by `--backup` when none is given.
+pub const DEFAULT_BACKUP_SUFFIX: &str = ".bak";
+
+/// Replaces the contents of an existing file with what `write` writes,
+/// without ever leaving it half written.
+///
+/// The new contents go to a temporary file in the same directory, which is
+/// given the permissions, owner and timestamps of the original, synced to
+/// disk and renamed over it. If anything fails, including a crash or a full
+/// disk, the original is left untouched. With `backup`, the original is kept
+/// next to the file under its name followed by that suffix.
+///
+/// A symbolic link is followed, so the file it points to is replaced and the
+/// link itself is kept.
+pub fn replace<P, F>(path: P, backup: Option<&str>, write: F) -> crate::Result<()>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&mut BufWriter<File>) -> crate::Result<()>,
+{
+    // The temporary file and the backup go next to the file itself
+    let path = &fs::canonicalize(path)?;
+    let metadata = fs::metadata(path)?;
+    let (temp_path, temp) = create_temp(path)?;
+
+    let replaced = write_temp(temp, &metadata, write).and_then(|_| {
+        if let Some(suffix) = backup {
+            back_up(path, suffix)?;
+        }
+        Ok(fs::rename(&temp_path, path)?)
+    });
+    if replaced.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    replaced?;
+
+    sync_parent(path)
+}
+
+/// Replaces the contents of an existing file with `bytes`, see [`replace`].
+pub fn write<P: AsRef<Path>>(path: P, backup: Option<&str>, bytes: &[u8]) -> crate::Result<()> {
+    replace(path, backup, |writer| Ok(writer.write_all(bytes)?))
+}
+
+/// Where the backup of `path` is kept with this suffix.
+pub fn backup_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+// Hidden, and next to the file so the rename cannot cross filesystems. The
+// name is random and the file must not exist yet, so nothing placed there
+// in advance, such as a symlink, can be written through.
+fn create_temp(path: &Path) -> io::Result<(PathBuf, File)> {
+    let mut options = File::options();
+    options.write(true).create_new(true);
+    // Only the owner can read it until it is given the original's permissions
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    loop {
+        let temp_path = temp_name(path);
+        match options.open(&temp_path) {
+            Ok(file) => return Ok((temp_path, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+fn temp_name(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.{:016x}.pngme-tmp", name, OsRng.next_u64()))
+}
+
+fn write_temp<F>(temp: File, original: &Metadata, write: F) -> crate::Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> crate::Result<()>,
+{
+    let mut writer = BufWriter::new(temp);
+    write(&mut writer)?;
+    let file = writer.into_inner().map_err(|err| err.into_error())?;
+
+    file.set_permissions(original.permissions())?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{MetadataExt, fchown};
+        // Only root may give a file away, so this is best effort
+        let _ = fchown(&file, Some(original.uid()), Some(original.gid()));
+    }
+    let mut times = FileTimes::new().set_modified(original.modified()?);
+    if let Ok(accessed) = original.accessed() {
+        times = times.set_accessed(accessed);
+    }
+    file.set_times(times)?;
+
+    file.sync_all()?;
+    Ok(())
+}
+
+// The new backup is made under a temporary name and renamed over the old
+// one, so a failure never leaves the file without any backup
+fn back_up(path: &Path, suffix: &str) -> io::Result<()> {
+    let temp_path = link_or_copy(path)?;
+    fs::rename(&temp_path, backup_path(path, suffix)).inspect_err(|_| {
+        let _ = fs::remove_file(&temp_path);
+    })
+}
+
+// The original stays in place until the rename, so it is linked rather than
+// moved, and copied where links are not supported
+fn link_or_copy(path: &Path) -> io::Result<PathBuf> {
+    loop {
+        let temp_path = temp_name(path);
+        match fs::hard_link(path, &temp_path) {
+            Ok(()) => return Ok(temp_path),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(_) => break,
+        }
+    }
+
+    let (temp_path, mut temp) = create_temp(path)?;
+    let copied = File::open(path)
+        .and_then(|mut original| io::copy(&mut original, &mut temp))
+        .and_then(|_| temp.set_permissions(fs::metadata(path)?.permissions()))
+        .and_then(|_| temp.sync_all());
+    match copied {
+        Ok(()) => Ok(temp_path),
+        Err(err) => {
+            let _ = fs::remove_file(&temp_path);
+            Err(err)
+        }
+    }
+}
+
+// Makes the rename itself durable
+fn sync_parent(path: &Path) -> crate::Result<()> {
+    #[cfg(unix)]
+    {
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        File::open(parent)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A file of its own in the temporary directory, removed with its
+    // leftovers when dropped
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str, contents: &[u8]) -> Scratch {
+            let dir =
+                std::env::temp_dir().join(format!("pngme-output-{}-{}", name, std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            let path = dir.join("image.png");
+            fs::write(&path, contents).unwrap();
+            Scratch(path)
+        }
+
+        fn leftovers(&self) -> Vec<String> {
+            let mut names: Vec<String> = fs::read_dir(self.0.parent().unwrap())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            names
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(self.0.parent().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_replaces_and_keeps_metadata() {
+        let scratch = Scratch::new("replace", b"original");
+        let modified =
+            std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        File::options()
+            .write(true)
+            .open(&scratch.0)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&scratch.0, fs::Permissions::from_mode(0o640)).unwrap();
+        }
+
+        write(&scratch.0, None, b"replaced").unwrap();
+
+        assert_eq!(fs::read(&scratch.0).unwrap(), b"replaced");
+        let metadata = fs::metadata(&scratch.0).unwrap();
+        assert_eq!(metadata.modified().unwrap(), modified);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o640);
+        }
+        assert_eq!(scratch.leftovers(), ["image.png"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_temp_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let scratch = Scratch::new("private", b"original");
+        fs::set_permissions(&scratch.0, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let (temp_path, temp) = create_temp(&scratch.0).unwrap();
+        let mode = temp.metadata().unwrap().permissions().mode();
+        fs::remove_file(temp_path).unwrap();
+        assert_eq!(mode & 0o077, 0);
+    }
+
+    #[test]
+    fn test_failure_leaves_original() {
+        let scratch = Scratch::new("failure", b"original");
+
+        let result = replace(&scratch.0, Some(".bak"), |writer| {
+            writer.write_all(b"half of it")?;
+            Err(crate::Error::BadSignature)
+        });
+
+        assert!(matches!(result, Err(crate::Error::BadSignature)));
+        assert_eq!(fs::read(&scratch.0).unwrap(), b"original");
+        assert_eq!(scratch.leftovers(), ["image.png"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_follows_symlinks() {
+        let scratch = Scratch::new("symlink", b"original");
+        let link = scratch.0.with_file_name("link.png");
+        std::os::unix::fs::symlink(&scratch.0, &link).unwrap();
+
+        write(&link, Some(DEFAULT_BACKUP_SUFFIX), b"replaced").unwrap();
+
+        assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!(fs::read(&link).unwrap(), b"replaced");
+        assert_eq!(
+            fs::read(backup_path(&scratch.0, DEFAULT_BACKUP_SUFFIX)).unwrap(),
+            b"original"
+        );
+        assert_eq!(
+            scratch.leftovers(),
+            ["image.png", "image.png.bak", "link.png"]
+        );
+    }
+
+    #[test]
+    fn test_keeps_backup() {
+        let scratch = Scratch::new("backup", b"first");
+
+        write(&scratch.0, Some(DEFAULT_BACKUP_SUFFIX), b"second").unwrap();
+        write(&scratch.0, Some(".orig"), b"third").unwrap();
+        write(&scratch.0, Some(DEFAULT_BACKUP_SUFFIX), b"fourth").unwrap();
+
+        assert_eq!(fs::read(&scratch.0).unwrap(), b"fourth");
+        let backup = |suffix| fs::read(backup_path(&scratch.0, suffix)).unwrap();
+        assert_eq!(backup(".bak"), b"third");
+        assert_eq!(backup(".orig"), b"second");
+        assert_eq!(
+            scratch.leftovers(),
+            ["image.png", "image.png.bak", "image.png.orig"]
+        );
+    }
+}
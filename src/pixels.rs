@@ -0,0 +1,642 @@
+use std::io::Read;
+
+use flate2::read::ZlibDecoder;
+
+use crate::{
+    Error,
+    ihdr::{ColorType, Ihdr},
+    png::{Png, PngRef},
+};
+
+/// Starting column, starting row, column step and row step of the seven
+/// Adam7 passes.
+pub(crate) const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// Decoded samples, one entry per channel of every pixel in row-major order.
+///
+/// Bit depths below 8 are unpacked to one sample per byte without rescaling,
+/// so a 1-bit image holds zeros and ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Samples {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+}
+
+impl Samples {
+    pub fn len(&self) -> usize {
+        match self {
+            Samples::U8(samples) => samples.len(),
+            Samples::U16(samples) => samples.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> u16 {
+        match self {
+            Samples::U8(samples) => samples[index] as u16,
+            Samples::U16(samples) => samples[index],
+        }
+    }
+
+    pub fn set(&mut self, index: usize, value: u16) {
+        match self {
+            Samples::U8(samples) => samples[index] = value as u8,
+            Samples::U16(samples) => samples[index] = value,
+        }
+    }
+}
+
+/// The pixels of a PNG, with what is needed to interpret them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    width: u32,
+    height: u32,
+    color_type: ColorType,
+    bit_depth: u8,
+    palette: Option<Vec<[u8; 3]>>,
+    samples: Samples,
+}
+
+impl Image {
+    /// Creates an image from unpacked samples, checking that their number
+    /// and range match the format.
+    pub fn new(
+        width: u32,
+        height: u32,
+        color_type: ColorType,
+        bit_depth: u8,
+        palette: Option<Vec<[u8; 3]>>,
+        samples: Samples,
+    ) -> crate::Result<Image> {
+        let expected = width as usize * height as usize * color_type.channels() as usize;
+        if samples.len() != expected {
+            return Err(Error::InvalidImageData(format!(
+                "expected {} samples, found {}",
+                expected,
+                samples.len()
+            )));
+        }
+        if !color_type.allowed_bit_depths().contains(&bit_depth) {
+            return Err(Error::InvalidImageData(format!(
+                "bit depth {} is not allowed for {} images",
+                bit_depth, color_type
+            )));
+        }
+        match (&samples, bit_depth) {
+            (Samples::U16(_), 16) => {}
+            (Samples::U8(values), depth) if depth <= 8 => {
+                if values.iter().any(|&v| v as u16 > max_value(depth)) {
+                    return Err(Error::InvalidImageData(format!(
+                        "sample out of range for bit depth {}",
+                        depth
+                    )));
+                }
+            }
+            _ => {
+                return Err(Error::InvalidImageData(format!(
+                    "sample type does not match bit depth {}",
+                    bit_depth
+                )));
+            }
+        }
+
+        Ok(Image {
+            width,
+            height,
+            color_type,
+            bit_depth,
+            palette,
+            samples,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn color_type(&self) -> ColorType {
+        self.color_type
+    }
+
+    pub fn bit_depth(&self) -> u8 {
+        self.bit_depth
+    }
+
+    pub fn channels(&self) -> usize {
+        self.color_type.channels() as usize
+    }
+
+    /// Palette entries for indexed images.
+    pub fn palette(&self) -> Option<&[[u8; 3]]> {
+        self.palette.as_deref()
+    }
+
+    pub fn samples(&self) -> &Samples {
+        &self.samples
+    }
+
+    pub fn samples_mut(&mut self) -> &mut Samples {
+        &mut self.samples
+    }
+
+    /// Largest value a sample can hold at this bit depth.
+    pub fn max_sample(&self) -> u16 {
+        max_value(self.bit_depth)
+    }
+
+    pub fn sample(&self, x: u32, y: u32, channel: usize) -> u16 {
+        self.samples.get(self.index(x, y, channel))
+    }
+
+    pub fn set_sample(&mut self, x: u32, y: u32, channel: usize, value: u16) {
+        let index = self.index(x, y, channel);
+        self.samples.set(index, value & self.max_sample());
+    }
+
+    /// The pixel at (x, y) as 8-bit RGBA, resolving palettes and scaling
+    /// other bit depths.
+    pub fn rgba8(&self, x: u32, y: u32) -> [u8; 4] {
+        let scale = |v: u16| match self.bit_depth {
+            16 => (v >> 8) as u8,
+            depth => (v as u32 * 255 / max_value(depth) as u32) as u8,
+        };
+        let s = |c| self.sample(x, y, c);
+        match self.color_type {
+            ColorType::Grayscale => [scale(s(0)), scale(s(0)), scale(s(0)), 255],
+            ColorType::GrayscaleAlpha => [scale(s(0)), scale(s(0)), scale(s(0)), scale(s(1))],
+            ColorType::Rgb => [scale(s(0)), scale(s(1)), scale(s(2)), 255],
+            ColorType::Rgba => [scale(s(0)), scale(s(1)), scale(s(2)), scale(s(3))],
+            ColorType::Indexed => {
+                let [r, g, b] = self
+                    .palette()
+                    .and_then(|palette| palette.get(s(0) as usize))
+                    .copied()
+                    .unwrap_or_default();
+                [r, g, b, 255]
+            }
+        }
+    }
+
+    fn index(&self, x: u32, y: u32, channel: usize) -> usize {
+        assert!(x < self.width && y < self.height && channel < self.channels());
+        (y as usize * self.width as usize + x as usize) * self.channels() + channel
+    }
+}
+
+impl Png {
+    /// Decodes the pixels: inflates the concatenated IDAT chunks, undoes the
+    /// scanline filters and Adam7 interlacing, and unpacks the samples.
+    pub fn decode_image(&self) -> crate::Result<Image> {
+        PngRef::from(self).decode_image()
+    }
+}
+
+impl PngRef<'_> {
+    /// Decodes the pixels, see [`Png::decode_image`].
+    pub fn decode_image(&self) -> crate::Result<Image> {
+        let ihdr = self.ihdr()?;
+
+        let compressed: Vec<u8> = self
+            .chunks()
+            .iter()
+            .filter(|c| c.chunk_type().as_str() == "IDAT")
+            .flat_map(|c| c.data().iter().copied())
+            .collect();
+        if compressed.is_empty() {
+            return Err(Error::ChunkNotFound("IDAT".to_string()));
+        }
+        // The size comes from the header, so the stream is never inflated
+        // past what the image can hold, however much it claims
+        let expected = raw_len(&ihdr).ok_or_else(|| {
+            Error::InvalidImageData(format!("{}x{} image is too large", ihdr.width, ihdr.height))
+        })?;
+        let mut raw = Vec::new();
+        ZlibDecoder::new(compressed.as_slice())
+            .take((expected as u64).saturating_add(1))
+            .read_to_end(&mut raw)
+            .map_err(|err| Error::InvalidImageData(format!("corrupt zlib stream: {}", err)))?;
+        if raw.len() > expected {
+            return Err(Error::InvalidImageData(format!(
+                "more than the {} bytes of scanlines expected",
+                expected
+            )));
+        }
+        if raw.len() < expected {
+            return Err(Error::InvalidImageData(format!(
+                "expected {} bytes of scanlines, found {}",
+                expected,
+                raw.len()
+            )));
+        }
+
+        let palette = match ihdr.color_type {
+            ColorType::Indexed => Some(self.palette()?),
+            _ => None,
+        };
+
+        let samples = if ihdr.is_interlaced() {
+            decode_interlaced(&ihdr, &raw)?
+        } else {
+            let (samples, _) = decode_pass(&ihdr, &raw, ihdr.width as usize, ihdr.height as usize)?;
+            samples
+        };
+
+        Image::new(
+            ihdr.width,
+            ihdr.height,
+            ihdr.color_type,
+            ihdr.bit_depth,
+            palette,
+            samples,
+        )
+    }
+
+    fn palette(&self) -> crate::Result<Vec<[u8; 3]>> {
+        let plte = self
+            .chunk_by_type("PLTE")
+            .ok_or_else(|| Error::ChunkNotFound("PLTE".to_string()))?;
+        Ok(plte
+            .data()
+            .chunks_exact(3)
+            .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+            .collect())
+    }
+}
+
+pub(crate) fn max_value(bit_depth: u8) -> u16 {
+    ((1u32 << bit_depth) - 1) as u16
+}
+
+/// Bytes per complete pixel, rounded up to 1, as used by the filters.
+pub(crate) fn filter_bpp(ihdr: &Ihdr) -> usize {
+    (ihdr.color_type.channels() as usize * ihdr.bit_depth as usize).div_ceil(8)
+}
+
+/// Bytes in one scanline of `width` pixels, without the filter byte.
+pub(crate) fn row_bytes(ihdr: &Ihdr, width: usize) -> usize {
+    (width * ihdr.color_type.channels() as usize * ihdr.bit_depth as usize).div_ceil(8)
+}
+
+// Pixels in an Adam7 pass of an image of the given size
+pub(crate) fn pass_size(pass: usize, width: usize, height: usize) -> (usize, usize) {
+    let (x0, y0, dx, dy) = ADAM7[pass];
+    (
+        width.saturating_sub(x0).div_ceil(dx),
+        height.saturating_sub(y0).div_ceil(dy),
+    )
+}
+
+// Bytes of filtered scanlines, filter bytes included, that the image
+// inflates to, or None if that overflows
+fn raw_len(ihdr: &Ihdr) -> Option<usize> {
+    let (width, height) = (ihdr.width as usize, ihdr.height as usize);
+    let bits = ihdr.color_type.channels() as usize * ihdr.bit_depth as usize;
+    let pass_len = |width: usize, height: usize| {
+        let stride = width.checked_mul(bits)?.div_ceil(8);
+        stride.checked_add(1)?.checked_mul(height)
+    };
+    if !ihdr.is_interlaced() {
+        return pass_len(width, height);
+    }
+    (0..ADAM7.len()).try_fold(0usize, |total, pass| match pass_size(pass, width, height) {
+        (0, _) | (_, 0) => Some(total),
+        (pass_width, pass_height) => total.checked_add(pass_len(pass_width, pass_height)?),
+    })
+}
+
+fn decode_interlaced(ihdr: &Ihdr, raw: &[u8]) -> crate::Result<Samples> {
+    let (width, height) = (ihdr.width as usize, ihdr.height as usize);
+    let channels = ihdr.color_type.channels() as usize;
+    let mut samples = match ihdr.bit_depth {
+        16 => Samples::U16(vec![0; width * height * channels]),
+        _ => Samples::U8(vec![0; width * height * channels]),
+    };
+
+    let mut offset = 0;
+    for (pass, &(x0, y0, dx, dy)) in ADAM7.iter().enumerate() {
+        let (pass_width, pass_height) = pass_size(pass, width, height);
+        if pass_width == 0 || pass_height == 0 {
+            continue;
+        }
+        let (pass_samples, used) = decode_pass(ihdr, &raw[offset..], pass_width, pass_height)?;
+        offset += used;
+
+        for py in 0..pass_height {
+            for px in 0..pass_width {
+                let (x, y) = (x0 + px * dx, y0 + py * dy);
+                for c in 0..channels {
+                    let value = pass_samples.get((py * pass_width + px) * channels + c);
+                    samples.set((y * width + x) * channels + c, value);
+                }
+            }
+        }
+    }
+    Ok(samples)
+}
+
+// Unfilters and unpacks one image (or Adam7 pass), returning the samples and
+// the number of raw bytes consumed
+fn decode_pass(
+    ihdr: &Ihdr,
+    raw: &[u8],
+    width: usize,
+    height: usize,
+) -> crate::Result<(Samples, usize)> {
+    let stride = row_bytes(ihdr, width);
+    let needed = (stride + 1) * height;
+    if raw.len() < needed {
+        return Err(Error::InvalidImageData(format!(
+            "expected {} bytes of scanlines, found {}",
+            needed,
+            raw.len()
+        )));
+    }
+
+    let bpp = filter_bpp(ihdr);
+    let mut previous = vec![0u8; stride];
+    let mut current = vec![0u8; stride];
+    let channels = ihdr.color_type.channels() as usize;
+    let mut samples = match ihdr.bit_depth {
+        16 => Samples::U16(Vec::with_capacity(width * height * channels)),
+        _ => Samples::U8(Vec::with_capacity(width * height * channels)),
+    };
+
+    for row in raw[..needed].chunks_exact(stride + 1) {
+        current.copy_from_slice(&row[1..]);
+        unfilter(row[0], bpp, &previous, &mut current)?;
+        unpack(&current, ihdr.bit_depth, width * channels, &mut samples);
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    Ok((samples, needed))
+}
+
+fn unfilter(filter: u8, bpp: usize, previous: &[u8], current: &mut [u8]) -> crate::Result<()> {
+    match filter {
+        0 => {}
+        1 => {
+            for i in bpp..current.len() {
+                current[i] = current[i].wrapping_add(current[i - bpp]);
+            }
+        }
+        2 => {
+            for i in 0..current.len() {
+                current[i] = current[i].wrapping_add(previous[i]);
+            }
+        }
+        3 => {
+            for i in 0..current.len() {
+                let left = if i >= bpp { current[i - bpp] } else { 0 };
+                let average = ((left as u16 + previous[i] as u16) / 2) as u8;
+                current[i] = current[i].wrapping_add(average);
+            }
+        }
+        4 => {
+            for i in 0..current.len() {
+                let (left, upper_left) = if i >= bpp {
+                    (current[i - bpp], previous[i - bpp])
+                } else {
+                    (0, 0)
+                };
+                current[i] = current[i].wrapping_add(paeth(left, previous[i], upper_left));
+            }
+        }
+        other => {
+            return Err(Error::InvalidImageData(format!(
+                "unknown filter type {}",
+                other
+            )));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// Appends the first `count` samples of a scanline
+fn unpack(row: &[u8], bit_depth: u8, count: usize, samples: &mut Samples) {
+    match samples {
+        Samples::U16(samples) => samples.extend(
+            row.chunks_exact(2)
+                .take(count)
+                .map(|b| u16::from_be_bytes([b[0], b[1]])),
+        ),
+        Samples::U8(samples) if bit_depth == 8 => samples.extend_from_slice(&row[..count]),
+        Samples::U8(samples) => {
+            let per_byte = 8 / bit_depth as usize;
+            let mask = max_value(bit_depth) as u8;
+            samples.extend((0..count).map(|i| {
+                let shift = 8 - bit_depth as usize * (i % per_byte + 1);
+                (row[i / per_byte] >> shift) & mask
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, str::FromStr};
+
+    use flate2::{Compression, write::ZlibEncoder};
+
+    use super::*;
+    use crate::{chunk::Chunk, chunk_type::ChunkType};
+
+    fn zlib(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn png(ihdr: Ihdr, extra: Vec<Chunk>, scanlines: &[u8]) -> Png {
+        let mut chunks = vec![Chunk::new(
+            ChunkType::from_str("IHDR").unwrap(),
+            ihdr.as_bytes(),
+        )];
+        chunks.extend(extra);
+        // Split the data over two IDATs to exercise the concatenation
+        let compressed = zlib(scanlines);
+        let (first, second) = compressed.split_at(compressed.len() / 2);
+        chunks.push(Chunk::new(
+            ChunkType::from_str("IDAT").unwrap(),
+            first.to_vec(),
+        ));
+        chunks.push(Chunk::new(
+            ChunkType::from_str("IDAT").unwrap(),
+            second.to_vec(),
+        ));
+        chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()));
+        Png::from_chunks(chunks)
+    }
+
+    fn ihdr(width: u32, height: u32, bit_depth: u8, color_type: ColorType) -> Ihdr {
+        Ihdr {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 0,
+        }
+    }
+
+    #[test]
+    fn test_decode_every_filter() {
+        // Rows [10, 20] and [30, 50] written with each filter on the second row
+        let first_row = [1, 10, 10];
+        for second_row in [
+            [0, 30, 50],
+            [1, 30, 20],
+            [2, 20, 30],
+            [3, 25, 25],
+            [4, 20, 20],
+        ] {
+            let scanlines: Vec<u8> = first_row.iter().chain(&second_row).copied().collect();
+            let png = png(ihdr(2, 2, 8, ColorType::Grayscale), vec![], &scanlines);
+
+            let image = png.decode_image().unwrap();
+            assert_eq!(
+                image.samples(),
+                &Samples::U8(vec![10, 20, 30, 50]),
+                "filter {}",
+                second_row[0]
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_sub_byte_depth() {
+        // 10 pixels of 1 bit: 1011001110, padded to two bytes
+        let png = png(
+            ihdr(10, 1, 1, ColorType::Grayscale),
+            vec![],
+            &[0, 0b1011_0011, 0b1000_0000],
+        );
+
+        let image = png.decode_image().unwrap();
+        assert_eq!(
+            image.samples(),
+            &Samples::U8(vec![1, 0, 1, 1, 0, 0, 1, 1, 1, 0])
+        );
+        assert_eq!(image.rgba8(0, 0), [255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_decode_sixteen_bit() {
+        let png = png(
+            ihdr(1, 1, 16, ColorType::Rgb),
+            vec![],
+            &[0, 0x12, 0x34, 0xFF, 0xFF, 0x00, 0x01],
+        );
+
+        let image = png.decode_image().unwrap();
+        assert_eq!(image.samples(), &Samples::U16(vec![0x1234, 0xFFFF, 0x0001]));
+    }
+
+    #[test]
+    fn test_decode_palette() {
+        let plte = Chunk::new(
+            ChunkType::from_str("PLTE").unwrap(),
+            vec![255, 0, 0, 0, 0, 255],
+        );
+        let png = png(
+            ihdr(4, 1, 2, ColorType::Indexed),
+            vec![plte],
+            &[0, 0b0001_0001],
+        );
+
+        let image = png.decode_image().unwrap();
+        assert_eq!(image.samples(), &Samples::U8(vec![0, 1, 0, 1]));
+        assert_eq!(image.rgba8(1, 0), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_decode_interlaced() {
+        // 3x3 grayscale image with pixel values 0..9, which only uses passes
+        // 1 (0,0), 4 (2,0), 5 (0,2) (2,2), 6 (1,0) (1,2) and 7 (row 1)
+        let mut header = ihdr(3, 3, 8, ColorType::Grayscale);
+        header.interlace_method = 1;
+        let scanlines = [
+            0, 0, // pass 1
+            0, 2, // pass 4
+            0, 6, 8, // pass 5
+            0, 1, 0, 7, // pass 6
+            0, 3, 4, 5, // pass 7
+        ];
+        let png = png(header, vec![], &scanlines);
+
+        let image = png.decode_image().unwrap();
+        assert_eq!(image.samples(), &Samples::U8((0..9).collect()));
+    }
+
+    #[test]
+    fn test_decode_image_file() {
+        let png = Png::try_from(&crate::png::tests::PNG_FILE[..]).unwrap();
+
+        let image = png.decode_image().unwrap();
+        assert_eq!((image.width(), image.height()), (50, 50));
+        assert_eq!(image.samples().len(), 50 * 50 * 4);
+    }
+
+    #[test]
+    fn test_truncated_image_data() {
+        let png = png(ihdr(2, 2, 8, ColorType::Grayscale), vec![], &[0, 1, 2, 0]);
+        assert!(matches!(
+            png.decode_image(),
+            Err(Error::InvalidImageData(_))
+        ));
+    }
+
+    #[test]
+    fn test_image_data_is_bounded() {
+        // Scanlines past what the header announces are never inflated
+        let mut scanlines = vec![0, 1, 2, 0, 3, 4];
+        scanlines.extend(vec![0; 1 << 20]);
+        let bomb = png(ihdr(2, 2, 8, ColorType::Grayscale), vec![], &scanlines);
+        assert!(matches!(
+            bomb.decode_image(),
+            Err(Error::InvalidImageData(_))
+        ));
+
+        // Nor is memory set aside for the samples of a huge image
+        for interlace_method in [0, 1] {
+            let huge = Ihdr {
+                interlace_method,
+                ..ihdr(0x7FFF_FFFF, 0x7FFF_FFFF, 16, ColorType::Rgba)
+            };
+            let huge = png(huge, vec![], &[0; 16]);
+            assert!(matches!(
+                huge.decode_image(),
+                Err(Error::InvalidImageData(_))
+            ));
+        }
+    }
+}
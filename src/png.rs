@@ -1,77 +1,83 @@
-use std::{
-    fmt::Display,
-    io::{Cursor, Read},
-};
+use std::{fmt::Display, io::Read};
 
-use crate::chunk::Chunk;
+use crate::{
+    Error,
+    chunk::{Chunk, ChunkRef},
+    ihdr::Ihdr,
+    reader::ChunkReader,
+};
 
 #[derive(Debug)]
 pub struct Png {
     signature_header: [u8; 8],
     chunks: Vec<Chunk>,
+    diagnostics: Vec<Diagnostic>,
 }
 
-impl TryFrom<&[u8]> for Png {
-    type Error = crate::Error;
-
-    // Required method
-    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        // Ensuring minimum length to read Signature Header
-        if value.len() < 8 {
-            return Err(
-                "Less than 8 bytes required for Signature Header. Invalid PNG file.".into(),
-            );
-        }
-
-        // Creating a reader to read all the bytes
-        let mut reader = Cursor::new(value);
+/// Damage found by [`Png::parse_lenient`] and [`PngRef::parse_lenient`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diagnostic {
+    /// The chunk at index `chunk` was kept although its CRC does not match.
+    CrcMismatch {
+        chunk: usize,
+        chunk_type: String,
+        offset: usize,
+        stored: u32,
+        computed: u32,
+    },
+    /// The file ends in the middle of a chunk, which was dropped.
+    TruncatedChunk { offset: usize, needed: usize },
+    /// Bytes between chunks that are not a chunk were skipped.
+    Garbage { offset: usize, length: usize },
+    /// Bytes after IEND were skipped.
+    TrailingData { offset: usize, length: usize },
+}
 
-        // Getting Signature Header and comparing to see if it's valid
-        let mut signature_header: [u8; 8] = [0; 8];
-        reader.read_exact(&mut signature_header)?;
-        if signature_header != Png::STANDARD_HEADER {
-            return Err("Signature Header mismatch.".into());
+impl Diagnostic {
+    /// Index of the chunk the damage is in, if it was kept.
+    pub fn chunk(&self) -> Option<usize> {
+        match self {
+            Diagnostic::CrcMismatch { chunk, .. } => Some(*chunk),
+            _ => None,
         }
+    }
+}
 
-        // Creating Vec for Chunks and start reading them
-        let mut chunks: Vec<Chunk> = Vec::new();
-        loop {
-            // Get length
-            let mut length_buff: [u8; 4] = [0; 4];
-            match reader.read_exact(&mut length_buff) {
-                Ok(_) => (),
-                Err(_) => {
-                    break;
-                }
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Diagnostic::CrcMismatch {
+                chunk_type,
+                offset,
+                stored,
+                computed,
+                ..
+            } => write!(
+                f,
+                "CRC mismatch in {} chunk at offset {}: stored {:08X}, computed {:08X}",
+                chunk_type, offset, stored, computed
+            ),
+            Diagnostic::TruncatedChunk { offset, needed } => write!(
+                f,
+                "truncated chunk at offset {}, {} more bytes needed",
+                offset, needed
+            ),
+            Diagnostic::Garbage { offset, length } => {
+                write!(f, "{} bytes of garbage at offset {}", length, offset)
             }
-            let data_length = u32::from_be_bytes(length_buff);
-
-            // Calculate total chunk size: length(4) + type(4) + data + crc(4)
-            let total_chunk_size = 4 + 4 + data_length + 4;
-
-            // Create buffer for the complete chunk including the length we already read
-            let mut complete_chunk: Vec<u8> = Vec::with_capacity(total_chunk_size as usize);
-            complete_chunk.extend_from_slice(&length_buff); // put back the length bytes
-
-            // Read the rest of the chunk
-            let mut remaining_bytes = vec![0; (total_chunk_size - 4) as usize];
-            reader.read_exact(&mut remaining_bytes)?;
-            complete_chunk.extend_from_slice(&remaining_bytes);
-
-            // call try_from from Chunk and try to form a Chunk
-            match Chunk::try_from(complete_chunk.as_slice()) {
-                Ok(chunk) => {
-                    chunks.push(chunk);
-                }
-                Err(err) => {
-                    return Err(format!("Error reading Chunk: {}.", err).into());
-                }
+            Diagnostic::TrailingData { offset, length } => {
+                write!(f, "{} bytes after IEND at offset {}", length, offset)
             }
         }
-        Ok(Png {
-            signature_header,
-            chunks,
-        })
+    }
+}
+
+impl TryFrom<&[u8]> for Png {
+    type Error = crate::Error;
+
+    // Required method
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        Png::from_reader(value)
     }
 }
 
@@ -103,18 +109,121 @@ impl Png {
         Png {
             signature_header,
             chunks: res_chunks,
+            diagnostics: Vec::new(),
         }
     }
 
+    /// Reads a whole PNG from any reader, checking every chunk.
+    pub fn from_reader<R: Read>(reader: R) -> crate::Result<Png> {
+        let chunks = ChunkReader::new(reader)?
+            .map(|chunk| chunk.map(|(_, chunk)| chunk))
+            .collect::<crate::Result<Vec<Chunk>>>()?;
+        Ok(Png::from_chunks(chunks))
+    }
+
+    /// Reads a damaged file as far as possible. Chunks with a wrong CRC are
+    /// kept, while truncated chunks, garbage between chunks and data after
+    /// IEND are skipped. Each problem is recorded in [`Png::diagnostics`].
+    ///
+    /// Only a missing or wrong signature is an error.
+    pub fn parse_lenient(value: &[u8]) -> crate::Result<Png> {
+        PngRef::parse_lenient(value).map(|png| png.to_png())
+    }
+
+    /// Damage found while reading the file, always empty unless it was read
+    /// with [`Png::parse_lenient`].
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
     pub fn append_chunk(&mut self, chunk: Chunk) {
         self.chunks.push(chunk);
     }
 
-    pub fn remove_first_chunk(&mut self, chunk_type: &str) -> crate::Result<Chunk> {
-        if self.chunks.is_empty() {
-            return Err("No chunks. Empty.".into());
+    /// Inserts a chunk where the PNG ordering rules allow it and returns its
+    /// index: IHDR first, PLTE before the image data, new IDATs right after
+    /// the existing ones, and ancillary chunks before IEND or before the
+    /// image data when the specification requires it.
+    ///
+    /// Fails if the chunk may only appear once and is already present.
+    pub fn insert_chunk(&mut self, chunk: Chunk) -> crate::Result<usize> {
+        let chunk_type = chunk.chunk_type().to_string();
+        if is_unique(&chunk_type) && self.chunk_by_type(&chunk_type).is_some() {
+            return Err(Error::ChunkOrder(format!(
+                "a PNG can only have one {} chunk",
+                chunk_type
+            )));
+        }
+
+        let first_of = |types: &[&str]| {
+            self.chunks
+                .iter()
+                .position(|c| types.contains(&c.chunk_type().to_string().as_str()))
+        };
+        let after_ihdr = first_of(&["IHDR"]).map_or(0, |pos| pos + 1);
+        let end = first_of(&["IEND"]).unwrap_or(self.chunks.len());
+
+        let index = match placement(&chunk_type) {
+            Placement::First => 0,
+            Placement::BeforePlte => first_of(&["PLTE", "IDAT", "IEND"])
+                .unwrap_or(self.chunks.len())
+                .max(after_ihdr),
+            Placement::Plte => first_of(&["IDAT", "IEND", "tRNS", "bKGD", "hIST"])
+                .unwrap_or(self.chunks.len())
+                .max(after_ihdr),
+            Placement::BeforeIdat => first_of(&["IDAT", "IEND"])
+                .unwrap_or(self.chunks.len())
+                .max(first_of(&["PLTE"]).map_or(after_ihdr, |pos| pos + 1)),
+            Placement::Idat => self
+                .chunks
+                .iter()
+                .rposition(|c| c.chunk_type().to_string() == "IDAT")
+                .map_or(end, |pos| pos + 1),
+            Placement::BeforeIend => end.max(after_ihdr),
+            Placement::Last => self.chunks.len(),
+        };
+
+        self.chunks.insert(index, chunk);
+        Ok(index)
+    }
+
+    /// Inserts a chunk at `index`, without checking the ordering rules.
+    pub fn insert_at(&mut self, index: usize, chunk: Chunk) -> crate::Result<()> {
+        if index > self.chunks.len() {
+            return Err(Error::ChunkOrder(format!(
+                "index {} is past the last chunk ({})",
+                index,
+                self.chunks.len()
+            )));
         }
+        self.chunks.insert(index, chunk);
+        Ok(())
+    }
+
+    /// Inserts a chunk right before the first chunk of type `chunk_type`.
+    pub fn insert_before(&mut self, chunk_type: &str, chunk: Chunk) -> crate::Result<usize> {
+        let index = self
+            .chunks
+            .iter()
+            .position(|c| c.chunk_type().to_string() == chunk_type)
+            .ok_or_else(|| Error::ChunkNotFound(chunk_type.to_string()))?;
+        self.chunks.insert(index, chunk);
+        Ok(index)
+    }
+
+    /// Inserts a chunk right after the last chunk of type `chunk_type`.
+    pub fn insert_after(&mut self, chunk_type: &str, chunk: Chunk) -> crate::Result<usize> {
+        let index = self
+            .chunks
+            .iter()
+            .rposition(|c| c.chunk_type().to_string() == chunk_type)
+            .ok_or_else(|| Error::ChunkNotFound(chunk_type.to_string()))?
+            + 1;
+        self.chunks.insert(index, chunk);
+        Ok(index)
+    }
 
+    pub fn remove_first_chunk(&mut self, chunk_type: &str) -> crate::Result<Chunk> {
         let chunk_type = String::from(chunk_type);
         // for (pos, chunk) in self.chunks.iter().enumerate() {}
         if let Some(pos) = self
@@ -124,10 +233,14 @@ impl Png {
         {
             Ok(self.chunks.remove(pos))
         } else {
-            Err("Chunk not found".into())
+            Err(Error::ChunkNotFound(chunk_type))
         }
     }
 
+    pub fn retain_chunks<F: FnMut(&Chunk) -> bool>(&mut self, f: F) {
+        self.chunks.retain(f);
+    }
+
     pub fn header(&self) -> &[u8; 8] {
         &self.signature_header
     }
@@ -142,6 +255,13 @@ impl Png {
             .find(|c| c.chunk_type().to_string() == chunk_type)
     }
 
+    pub fn ihdr(&self) -> crate::Result<Ihdr> {
+        let chunk = self
+            .chunk_by_type(Ihdr::CHUNK_TYPE)
+            .ok_or_else(|| Error::ChunkNotFound(Ihdr::CHUNK_TYPE.to_string()))?;
+        Ihdr::try_from(chunk)
+    }
+
     pub fn as_bytes(&self) -> Vec<u8> {
         let mut bytes = self.header().to_vec();
 
@@ -153,8 +273,219 @@ impl Png {
     }
 }
 
+/// A PNG borrowed from the bytes it was read from, such as a file in memory
+/// or memory-mapped, whose chunks copy none of their data.
+#[derive(Debug, Clone)]
+pub struct PngRef<'a> {
+    chunks: Vec<ChunkRef<'a>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> From<&'a Png> for PngRef<'a> {
+    fn from(png: &'a Png) -> PngRef<'a> {
+        PngRef {
+            chunks: png.chunks.iter().map(ChunkRef::from).collect(),
+            diagnostics: png.diagnostics.clone(),
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for PngRef<'a> {
+    type Error = crate::Error;
+
+    fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
+        if !value.starts_with(&Png::STANDARD_HEADER) {
+            return Err(Error::BadSignature);
+        }
+
+        let mut chunks = Vec::new();
+        let mut offset = Png::STANDARD_HEADER.len();
+        while offset < value.len() {
+            let chunk =
+                ChunkRef::try_from(&value[offset..]).map_err(|err| err.at_offset(offset))?;
+            offset += 12 + chunk.data().len();
+            chunks.push(chunk);
+        }
+        Ok(PngRef {
+            chunks,
+            diagnostics: Vec::new(),
+        })
+    }
+}
+
+// How many times the size of the file may be checksummed while looking for
+// intact chunks after damage. Every offset can claim a span reaching the end
+// of the file, so without a bound a crafted file takes quadratic time.
+const RESYNC_BUDGET: usize = 4;
+
+// Cheap test run before a CRC is computed at an offset while resyncing: the
+// type must be four ASCII letters and the data must fit in what is left.
+// Returns the bytes the CRC would cover.
+fn candidate_span(bytes: &[u8]) -> Option<usize> {
+    let length = u32::from_be_bytes(*bytes.first_chunk::<4>()?) as usize;
+    let chunk_type = bytes.get(4..8)?;
+    (chunk_type.iter().all(u8::is_ascii_alphabetic) && length + 12 <= bytes.len())
+        .then_some(length + 4)
+}
+
+impl<'a> PngRef<'a> {
+    /// Like [`Png::parse_lenient`], without copying any chunk.
+    pub fn parse_lenient(value: &'a [u8]) -> crate::Result<PngRef<'a>> {
+        if !value.starts_with(&Png::STANDARD_HEADER) {
+            return Err(Error::BadSignature);
+        }
+
+        let mut chunks: Vec<ChunkRef> = Vec::new();
+        let mut diagnostics = Vec::new();
+        let mut offset = Png::STANDARD_HEADER.len();
+        let mut budget = value.len().saturating_mul(RESYNC_BUDGET);
+        while offset < value.len() {
+            if chunks
+                .last()
+                .is_some_and(|c| c.chunk_type().as_str() == "IEND")
+            {
+                diagnostics.push(Diagnostic::TrailingData {
+                    offset,
+                    length: value.len() - offset,
+                });
+                break;
+            }
+
+            match ChunkRef::parse(&value[offset..], false) {
+                Ok(chunk) => {
+                    if chunk.crc() != chunk.computed_crc() {
+                        diagnostics.push(Diagnostic::CrcMismatch {
+                            chunk: chunks.len(),
+                            chunk_type: chunk.chunk_type().to_string(),
+                            offset,
+                            stored: chunk.crc(),
+                            computed: chunk.computed_crc(),
+                        });
+                    }
+                    offset += 12 + chunk.data().len();
+                    chunks.push(chunk);
+                }
+                // Skip to the next intact chunk. Without one, or once the
+                // budget is spent, this is the last chunk, cut short or
+                // damaged beyond use.
+                Err(err) => {
+                    let next =
+                        (offset + 1..value.len()).find(|&at| match candidate_span(&value[at..]) {
+                            Some(span) if span <= budget => {
+                                budget -= span;
+                                ChunkRef::try_from(&value[at..]).is_ok()
+                            }
+                            _ => false,
+                        });
+                    match (next, err) {
+                        (None, Error::TruncatedChunk { needed, .. }) => {
+                            diagnostics.push(Diagnostic::TruncatedChunk { offset, needed });
+                            break;
+                        }
+                        (next, _) => {
+                            let next = next.unwrap_or(value.len());
+                            diagnostics.push(Diagnostic::Garbage {
+                                offset,
+                                length: next - offset,
+                            });
+                            offset = next;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(PngRef {
+            chunks,
+            diagnostics,
+        })
+    }
+
+    pub fn chunks(&self) -> &[ChunkRef<'a>] {
+        &self.chunks
+    }
+
+    /// Damage found while reading, see [`Png::diagnostics`].
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&ChunkRef<'a>> {
+        self.chunks
+            .iter()
+            .find(|c| c.chunk_type().as_str() == chunk_type)
+    }
+
+    pub fn ihdr(&self) -> crate::Result<Ihdr> {
+        let chunk = self
+            .chunk_by_type(Ihdr::CHUNK_TYPE)
+            .ok_or_else(|| Error::ChunkNotFound(Ihdr::CHUNK_TYPE.to_string()))?;
+        Ihdr::try_from(chunk.data())
+    }
+
+    /// Copies every chunk into an owned [`Png`], diagnostics included.
+    pub fn to_png(&self) -> Png {
+        Png {
+            signature_header: Png::STANDARD_HEADER,
+            chunks: self.chunks.iter().map(ChunkRef::to_chunk).collect(),
+            diagnostics: self.diagnostics.clone(),
+        }
+    }
+}
+
+// Where the specification allows a chunk type to appear
+pub(crate) enum Placement {
+    First,
+    BeforePlte,
+    Plte,
+    BeforeIdat,
+    Idat,
+    BeforeIend,
+    Last,
+}
+
+pub(crate) fn placement(chunk_type: &str) -> Placement {
+    match chunk_type {
+        "IHDR" => Placement::First,
+        "cHRM" | "gAMA" | "iCCP" | "sBIT" | "sRGB" | "cICP" | "mDCV" | "cLLI" => {
+            Placement::BeforePlte
+        }
+        "PLTE" => Placement::Plte,
+        "bKGD" | "hIST" | "tRNS" | "pHYs" | "sPLT" | "oFFs" | "pCAL" | "sCAL" => {
+            Placement::BeforeIdat
+        }
+        "IDAT" => Placement::Idat,
+        "IEND" => Placement::Last,
+        _ => Placement::BeforeIend,
+    }
+}
+
+// Chunk types that may appear at most once in a file
+pub(crate) fn is_unique(chunk_type: &str) -> bool {
+    matches!(
+        chunk_type,
+        "IHDR"
+            | "PLTE"
+            | "IEND"
+            | "cHRM"
+            | "gAMA"
+            | "iCCP"
+            | "sBIT"
+            | "sRGB"
+            | "cICP"
+            | "mDCV"
+            | "cLLI"
+            | "bKGD"
+            | "hIST"
+            | "tRNS"
+            | "pHYs"
+            | "tIME"
+            | "eXIf"
+    )
+}
+
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use crate::chunk::Chunk;
     use crate::chunk_type::ChunkType;
@@ -249,6 +580,162 @@ mod tests {
         assert!(png.is_err());
     }
 
+    #[test]
+    fn test_invalid_header_is_bad_signature() {
+        let png = Png::try_from(&[13, 80, 78, 71, 13, 10, 26, 10][..]);
+
+        assert!(matches!(png, Err(Error::BadSignature)));
+    }
+
+    #[test]
+    fn test_truncated_chunk() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.truncate(bytes.len() - 5);
+
+        let png = Png::try_from(bytes.as_ref());
+
+        assert!(matches!(
+            png,
+            Err(Error::TruncatedChunk { offset, needed: 5 }) if offset == PNG_FILE.len() - 12
+        ));
+    }
+
+    #[test]
+    fn test_crc_mismatch_reports_offset() {
+        let mut bytes = PNG_FILE.to_vec();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+
+        let png = Png::try_from(bytes.as_ref());
+
+        match png {
+            Err(Error::CrcMismatch {
+                chunk_type, offset, ..
+            }) => {
+                assert_eq!(chunk_type, "IEND");
+                assert_eq!(offset, PNG_FILE.len() - 12);
+            }
+            other => panic!("expected CrcMismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_lenient_keeps_bad_crc() {
+        let mut bytes = PNG_FILE.to_vec();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+
+        let png = Png::parse_lenient(&bytes).unwrap();
+        let intact = Png::try_from(PNG_FILE.as_ref()).unwrap();
+        assert_eq!(png.chunks().len(), intact.chunks().len());
+        assert!(matches!(
+            png.diagnostics(),
+            [Diagnostic::CrcMismatch { chunk_type, .. }] if chunk_type == "IEND"
+        ));
+        assert_eq!(png.diagnostics()[0].chunk(), Some(png.chunks().len() - 1));
+        assert!(intact.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn test_parse_lenient_skips_damage() {
+        // Garbage before IEND, then junk after it
+        let iend = PNG_FILE.len() - 12;
+        let mut bytes = PNG_FILE[..iend].to_vec();
+        bytes.extend_from_slice(b"\0\0\0\x05@#!*!");
+        bytes.extend_from_slice(&PNG_FILE[iend..]);
+        bytes.extend_from_slice(b"trailing");
+
+        let png = Png::parse_lenient(&bytes).unwrap();
+        assert_eq!(
+            png.diagnostics(),
+            [
+                Diagnostic::Garbage {
+                    offset: iend,
+                    length: 9
+                },
+                Diagnostic::TrailingData {
+                    offset: iend + 21,
+                    length: 8
+                },
+            ]
+        );
+        assert_eq!(png.as_bytes(), PNG_FILE);
+
+        // A chunk cut short by the end of the file
+        let png = Png::parse_lenient(&PNG_FILE[..PNG_FILE.len() - 3]).unwrap();
+        assert_eq!(
+            png.diagnostics(),
+            [Diagnostic::TruncatedChunk {
+                offset: iend,
+                needed: 3
+            }]
+        );
+        assert_eq!(
+            png.chunks().len() + 1,
+            Png::try_from(PNG_FILE.as_ref()).unwrap().chunks().len()
+        );
+
+        assert!(matches!(
+            Png::parse_lenient(&bytes[1..]),
+            Err(Error::BadSignature)
+        ));
+    }
+
+    #[test]
+    fn test_parse_lenient_resync_is_bounded() {
+        // Every offset claims a plausible chunk reaching far into the file
+        let mut bytes = Png::STANDARD_HEADER.to_vec();
+        bytes.push(0);
+        while bytes.len() < 4 << 20 {
+            bytes.extend_from_slice(&(512u32 << 10).to_be_bytes());
+            bytes.extend_from_slice(b"aaaa");
+        }
+
+        let start = std::time::Instant::now();
+        let png = PngRef::parse_lenient(&bytes).unwrap();
+        assert!(png.chunks().is_empty());
+        assert_eq!(
+            png.diagnostics(),
+            [Diagnostic::Garbage {
+                offset: Png::STANDARD_HEADER.len(),
+                length: bytes.len() - Png::STANDARD_HEADER.len()
+            }]
+        );
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_png_ref_borrows_chunks() {
+        let png = PngRef::try_from(PNG_FILE.as_ref()).unwrap();
+        let owned = Png::try_from(PNG_FILE.as_ref()).unwrap();
+        assert_eq!(png.to_png().as_bytes(), owned.as_bytes());
+
+        let mut offset = 8;
+        for chunk in png.chunks() {
+            assert_eq!(chunk.data().as_ptr(), PNG_FILE[offset + 8..].as_ptr());
+            offset += 12 + chunk.data().len();
+        }
+
+        let mut bytes = PNG_FILE.to_vec();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        assert!(matches!(
+            PngRef::try_from(bytes.as_slice()),
+            Err(Error::CrcMismatch { offset, .. }) if offset == PNG_FILE.len() - 12
+        ));
+        assert!(matches!(
+            PngRef::try_from(&PNG_FILE[..PNG_FILE.len() - 5]),
+            Err(Error::TruncatedChunk { offset, needed: 5 }) if offset == PNG_FILE.len() - 12
+        ));
+
+        let damaged = PngRef::parse_lenient(&bytes).unwrap();
+        assert_eq!(
+            damaged.diagnostics(),
+            Png::parse_lenient(&bytes).unwrap().diagnostics()
+        );
+        assert_eq!(damaged.chunks().len(), owned.chunks().len());
+    }
+
     #[test]
     fn test_list_chunks() {
         let png = testing_png();
@@ -273,6 +760,78 @@ mod tests {
         assert_eq!(&chunk.data_as_string().unwrap(), "Message");
     }
 
+    fn chunk_types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect()
+    }
+
+    fn image_png() -> Png {
+        Png::from_chunks(vec![
+            chunk_from_strings("IHDR", "header").unwrap(),
+            chunk_from_strings("IDAT", "pixels").unwrap(),
+            chunk_from_strings("IDAT", "more pixels").unwrap(),
+            chunk_from_strings("IEND", "").unwrap(),
+        ])
+    }
+
+    #[test]
+    fn test_insert_chunk_follows_ordering_rules() {
+        let mut png = image_png();
+        png.insert_chunk(chunk_from_strings("ruSt", "Message").unwrap())
+            .unwrap();
+        png.insert_chunk(chunk_from_strings("tRNS", "alpha").unwrap())
+            .unwrap();
+        png.insert_chunk(chunk_from_strings("PLTE", "palette").unwrap())
+            .unwrap();
+        png.insert_chunk(chunk_from_strings("gAMA", "gamma").unwrap())
+            .unwrap();
+        png.insert_chunk(chunk_from_strings("IDAT", "last pixels").unwrap())
+            .unwrap();
+
+        assert_eq!(
+            chunk_types(&png),
+            vec![
+                "IHDR", "gAMA", "PLTE", "tRNS", "IDAT", "IDAT", "IDAT", "ruSt", "IEND"
+            ]
+        );
+        assert_eq!(png.chunks()[6].data(), b"last pixels");
+    }
+
+    #[test]
+    fn test_insert_chunk_rejects_duplicates() {
+        let mut png = image_png();
+        let result = png.insert_chunk(chunk_from_strings("IHDR", "again").unwrap());
+
+        assert!(matches!(result, Err(Error::ChunkOrder(_))));
+        assert_eq!(png.chunks().len(), 4);
+    }
+
+    #[test]
+    fn test_insert_before_and_after() {
+        let mut png = image_png();
+        png.insert_before("IDAT", chunk_from_strings("FrSt", "a").unwrap())
+            .unwrap();
+        png.insert_after("IDAT", chunk_from_strings("LASt", "b").unwrap())
+            .unwrap();
+        png.insert_at(0, chunk_from_strings("ZeRo", "c").unwrap())
+            .unwrap();
+
+        assert_eq!(
+            chunk_types(&png),
+            vec!["ZeRo", "IHDR", "FrSt", "IDAT", "IDAT", "LASt", "IEND"]
+        );
+        assert!(
+            png.insert_at(10, chunk_from_strings("FaIl", "d").unwrap())
+                .is_err()
+        );
+        assert!(
+            png.insert_before("PLTE", chunk_from_strings("FaIl", "d").unwrap())
+                .is_err()
+        );
+    }
+
     #[test]
     fn test_remove_first_chunk() {
         let mut png = testing_png();
@@ -282,12 +841,29 @@ mod tests {
         assert!(chunk.is_none());
     }
 
+    #[test]
+    fn test_retain_chunks() {
+        let mut png = testing_png();
+        png.retain_chunks(|c| c.chunk_type().to_string() != "miDl");
+        assert_eq!(png.chunks().len(), 2);
+        assert!(png.chunk_by_type("miDl").is_none());
+    }
+
     #[test]
     fn test_png_from_image_file() {
         let png = Png::try_from(&PNG_FILE[..]);
         assert!(png.is_ok());
     }
 
+    #[test]
+    fn test_ihdr() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let ihdr = png.ihdr().unwrap();
+        assert_eq!(ihdr.to_string(), "50x50, 8-bit RGBA, non-interlaced");
+
+        assert!(testing_png().ihdr().is_err());
+    }
+
     #[test]
     fn test_as_bytes() {
         let png = Png::try_from(&PNG_FILE[..]).unwrap();
@@ -315,7 +891,7 @@ mod tests {
     }
 
     // This is the raw bytes for a shrunken version of the `dice.png` image on Wikipedia
-    const PNG_FILE: [u8; 4803] = [
+    pub(crate) const PNG_FILE: [u8; 4803] = [
         137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0, 50, 0, 0, 0, 50, 8,
         6, 0, 0, 0, 30, 63, 136, 177, 0, 0, 0, 1, 115, 82, 71, 66, 0, 174, 206, 28, 233, 0, 0, 0,
         4, 103, 65, 77, 65, 0, 0, 177, 143, 11, 252, 97, 5, 0, 0, 0, 9, 112, 72, 89, 115, 0, 0, 14,
@@ -0,0 +1,205 @@
+use std::io::{self, Read};
+
+use crate::{Error, chunk::Chunk, chunk_type::ChunkType, png::Png};
+
+/// Reads the chunks of a PNG one at a time from any reader, such as a large
+/// file or a pipe, without holding more than one chunk in memory.
+///
+/// The signature is checked when the reader is created and each CRC as its
+/// chunk is read. Every chunk comes with the offset it starts at. The first
+/// error ends the iteration.
+pub struct ChunkReader<R> {
+    reader: R,
+    offset: u64,
+    finished: bool,
+}
+
+impl<R: Read> ChunkReader<R> {
+    /// Reads and checks the PNG signature.
+    pub fn new(mut reader: R) -> crate::Result<ChunkReader<R>> {
+        let mut signature = [0; 8];
+        if read_full(&mut reader, &mut signature)? < signature.len()
+            || signature != Png::STANDARD_HEADER
+        {
+            return Err(Error::BadSignature);
+        }
+        Ok(ChunkReader {
+            reader,
+            offset: signature.len() as u64,
+            finished: false,
+        })
+    }
+
+    /// Number of bytes read so far.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    // None at the end of the input, if it falls between two chunks
+    fn read_chunk(&mut self) -> crate::Result<Option<(u64, Chunk)>> {
+        let offset = self.offset;
+        let truncated = |needed: usize| Error::TruncatedChunk {
+            offset: offset as usize,
+            needed,
+        };
+
+        let mut header = [0; 8];
+        match read_full(&mut self.reader, &mut header)? {
+            0 => return Ok(None),
+            read if read < header.len() => return Err(truncated(12 - read)),
+            _ => {}
+        }
+        let length = u32::from_be_bytes(header[..4].try_into().unwrap());
+        if length > i32::MAX as u32 {
+            return Err(Error::ChunkTooLong {
+                offset: offset as usize,
+                length,
+            });
+        }
+        let length = length as usize;
+
+        // The data grows as it arrives, so a bogus length cannot make us
+        // allocate gigabytes up front
+        let mut data = Vec::new();
+        let read = (&mut self.reader)
+            .take(length as u64)
+            .read_to_end(&mut data)?;
+        let mut crc = [0; 4];
+        let crc_read = if read == length {
+            read_full(&mut self.reader, &mut crc)?
+        } else {
+            0
+        };
+        if read < length || crc_read < crc.len() {
+            return Err(truncated(length - read + crc.len() - crc_read));
+        }
+
+        let chunk_type = ChunkType::try_from(<[u8; 4]>::try_from(&header[4..]).unwrap())?;
+        let chunk = Chunk::new(chunk_type, data);
+        let stored = u32::from_be_bytes(crc);
+        if chunk.crc() != stored {
+            return Err(Error::CrcMismatch {
+                chunk_type: chunk.chunk_type().to_string(),
+                stored,
+                computed: chunk.crc(),
+                offset: offset as usize,
+            });
+        }
+
+        self.offset += (12 + length) as u64;
+        Ok(Some((offset, chunk)))
+    }
+}
+
+impl<R: Read> Iterator for ChunkReader<R> {
+    type Item = crate::Result<(u64, Chunk)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let result = self.read_chunk().transpose();
+        if !matches!(result, Some(Ok(_))) {
+            self.finished = true;
+        }
+        result
+    }
+}
+
+// Like read_exact, but returns how much was read when the input ends early
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::tests::PNG_FILE;
+
+    // Hands out one byte per read, like a slow pipe
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match (self.0.split_first(), buf.first_mut()) {
+                (Some((&byte, rest)), Some(slot)) => {
+                    *slot = byte;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn test_reads_chunks_with_offsets() {
+        let png = Png::try_from(PNG_FILE.as_ref()).unwrap();
+        let chunks: Vec<(u64, Chunk)> = ChunkReader::new(Trickle(&PNG_FILE))
+            .unwrap()
+            .collect::<crate::Result<_>>()
+            .unwrap();
+
+        assert_eq!(chunks.len(), png.chunks().len());
+        let mut offset = 8;
+        for ((at, chunk), expected) in chunks.iter().zip(png.chunks()) {
+            assert_eq!(*at, offset);
+            assert_eq!(chunk, expected);
+            offset += chunk.as_bytes().len() as u64;
+        }
+        assert_eq!(offset, PNG_FILE.len() as u64);
+    }
+
+    #[test]
+    fn test_bad_signature() {
+        assert!(matches!(
+            ChunkReader::new(&PNG_FILE[1..]),
+            Err(Error::BadSignature)
+        ));
+        assert!(matches!(
+            ChunkReader::new(&PNG_FILE[..5]),
+            Err(Error::BadSignature)
+        ));
+    }
+
+    #[test]
+    fn test_stops_at_first_error() {
+        let iend = PNG_FILE.len() - 12;
+        let mut reader = ChunkReader::new(&PNG_FILE[..PNG_FILE.len() - 2]).unwrap();
+        let last = reader.by_ref().last().unwrap();
+        assert!(matches!(
+            last,
+            Err(Error::TruncatedChunk { offset, needed: 2 }) if offset == iend
+        ));
+        assert!(reader.next().is_none());
+
+        let mut bytes = PNG_FILE.to_vec();
+        bytes[iend + 11] ^= 1;
+        let last = ChunkReader::new(bytes.as_slice()).unwrap().last().unwrap();
+        assert!(matches!(
+            last,
+            Err(Error::CrcMismatch { offset, .. }) if offset == iend
+        ));
+
+        let mut bytes = PNG_FILE.to_vec();
+        bytes[iend..iend + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+        let last = ChunkReader::new(bytes.as_slice()).unwrap().last().unwrap();
+        assert!(matches!(
+            last,
+            Err(Error::ChunkTooLong { offset, length: u32::MAX }) if offset == iend
+        ));
+    }
+}
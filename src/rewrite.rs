@@ -0,0 +1,200 @@
+use std::io::{Read, Write};
+
+use crate::{
+    Error,
+    chunk::Chunk,
+    png::{Placement, Png, is_unique, placement},
+    reader::ChunkReader,
+};
+
+/// Writes a PNG one chunk at a time, starting with the signature.
+pub struct ChunkWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> ChunkWriter<W> {
+    pub fn new(mut writer: W) -> crate::Result<ChunkWriter<W>> {
+        writer.write_all(&Png::STANDARD_HEADER)?;
+        Ok(ChunkWriter { writer })
+    }
+
+    pub fn write_chunk(&mut self, chunk: &Chunk) -> crate::Result<()> {
+        // Written in pieces, to avoid copying the data as Chunk::as_bytes does
+        self.writer.write_all(&chunk.length().to_be_bytes())?;
+        self.writer.write_all(&chunk.chunk_type().bytes())?;
+        self.writer.write_all(chunk.data())?;
+        self.writer.write_all(&chunk.crc().to_be_bytes())?;
+        Ok(())
+    }
+
+    /// Flushes what was written and returns the writer.
+    pub fn finish(mut self) -> crate::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Copies a PNG from `input` to `output` one chunk at a time, so files of
+/// any size are edited in constant memory.
+///
+/// `edit` is called with every chunk and returns the chunks to write in its
+/// place: none to drop it, or others around it to insert them. It is called
+/// one last time with `None` at the end of the file, to append chunks.
+pub fn rewrite<R, W, F>(input: R, output: W, mut edit: F) -> crate::Result<W>
+where
+    R: Read,
+    W: Write,
+    F: FnMut(Option<Chunk>) -> crate::Result<Vec<Chunk>>,
+{
+    let mut writer = ChunkWriter::new(output)?;
+    for chunk in ChunkReader::new(input)? {
+        let (_, chunk) = chunk?;
+        for chunk in edit(Some(chunk))? {
+            writer.write_chunk(&chunk)?;
+        }
+    }
+    for chunk in edit(None)? {
+        writer.write_chunk(&chunk)?;
+    }
+    writer.finish()
+}
+
+/// Inserts chunks of one type during a [`rewrite`], where
+/// [`Png::insert_chunk`] would put them.
+pub struct Inserter {
+    chunks: Vec<Chunk>,
+    placement: Placement,
+    unique: bool,
+    previous: Option<String>,
+}
+
+impl Inserter {
+    pub fn new(chunks: Vec<Chunk>) -> Inserter {
+        let chunk_type = chunks
+            .first()
+            .map(|c| c.chunk_type().to_string())
+            .unwrap_or_default();
+        Inserter {
+            placement: placement(&chunk_type),
+            unique: is_unique(&chunk_type),
+            chunks,
+            previous: None,
+        }
+    }
+
+    /// The chunks to write in place of `next`: the inserted ones if they go
+    /// right before it, then `next` itself.
+    pub fn edit(&mut self, next: Option<Chunk>) -> crate::Result<Vec<Chunk>> {
+        let Some(next) = next else {
+            return Ok(std::mem::take(&mut self.chunks));
+        };
+        let next_type = next.chunk_type().to_string();
+        let Some(inserted_type) = self.chunks.first().map(|c| c.chunk_type().to_string()) else {
+            return Ok(vec![next]);
+        };
+        if self.unique && next_type == inserted_type {
+            return Err(Error::ChunkOrder(format!(
+                "a PNG can only have one {} chunk",
+                next_type
+            )));
+        }
+
+        let before = match self.placement {
+            Placement::First => true,
+            // Nothing but IHDR goes first
+            _ if self.previous.is_none() => false,
+            Placement::BeforePlte => matches!(next_type.as_str(), "PLTE" | "IDAT" | "IEND"),
+            Placement::Plte => {
+                matches!(
+                    next_type.as_str(),
+                    "IDAT" | "IEND" | "tRNS" | "bKGD" | "hIST"
+                )
+            }
+            Placement::BeforeIdat => matches!(next_type.as_str(), "IDAT" | "IEND"),
+            Placement::Idat => {
+                next_type == "IEND"
+                    || (self.previous.as_deref() == Some("IDAT") && next_type != "IDAT")
+            }
+            Placement::BeforeIend => next_type == "IEND",
+            Placement::Last => false,
+        };
+        self.previous = Some(next_type);
+
+        let mut chunks = if before {
+            std::mem::take(&mut self.chunks)
+        } else {
+            Vec::new()
+        };
+        chunks.push(next);
+        Ok(chunks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::{chunk_type::ChunkType, png::tests::PNG_FILE};
+
+    fn chunk(chunk_type: &str, data: &str) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.into())
+    }
+
+    fn image_png() -> Vec<u8> {
+        Png::from_chunks(vec![
+            chunk("IHDR", "header"),
+            chunk("IDAT", "pixels"),
+            chunk("IDAT", "more pixels"),
+            chunk("IEND", ""),
+        ])
+        .as_bytes()
+    }
+
+    #[test]
+    fn test_rewrite_unchanged() {
+        let output = rewrite(PNG_FILE.as_ref(), Vec::new(), |chunk| {
+            Ok(chunk.into_iter().collect())
+        })
+        .unwrap();
+        assert_eq!(output, PNG_FILE);
+    }
+
+    #[test]
+    fn test_inserts_like_insert_chunk() {
+        for (chunk_type, count) in [("ruSt", 2), ("gAMA", 1), ("IDAT", 1), ("tRNS", 1)] {
+            let inserted = || (0..count).map(|i| chunk(chunk_type, &i.to_string()));
+
+            let mut expected = Png::try_from(image_png().as_slice()).unwrap();
+            for chunk in inserted() {
+                expected.insert_chunk(chunk).unwrap();
+            }
+            let mut inserter = Inserter::new(inserted().collect());
+            let output = rewrite(image_png().as_slice(), Vec::new(), |c| inserter.edit(c)).unwrap();
+
+            assert_eq!(output, expected.as_bytes(), "inserting {}", chunk_type);
+        }
+
+        let mut inserter = Inserter::new(vec![chunk("IHDR", "again")]);
+        let result = rewrite(image_png().as_slice(), Vec::new(), |c| inserter.edit(c));
+        assert!(matches!(result, Err(Error::ChunkOrder(_))));
+    }
+
+    #[test]
+    fn test_drops_and_appends() {
+        let output = rewrite(image_png().as_slice(), Vec::new(), |next| match next {
+            Some(c) if c.chunk_type().to_string() == "IDAT" => Ok(Vec::new()),
+            Some(c) => Ok(vec![c]),
+            None => Ok(vec![chunk("tEXt", "after IEND")]),
+        })
+        .unwrap();
+
+        let png = Png::try_from(output.as_slice()).unwrap();
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IHDR", "IEND", "tEXt"]);
+    }
+}
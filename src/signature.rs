@@ -0,0 +1,541 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    str::FromStr,
+};
+
+use ed25519_dalek::{Signature, Signer, Verifier};
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+use rand_core::OsRng;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    Error,
+    chunk::{Chunk, ChunkRef},
+    chunk_type::ChunkType,
+    png::{Png, PngRef},
+};
+
+/// Private, ancillary chunk holding the signature. It is marked unsafe to copy
+/// since any edit of the image invalidates it.
+pub const SIGNATURE_CHUNK: &str = "siGN";
+
+// Signature chunk layout:
+//
+//   version(1) public key(32) stream digest(32) count(4) entries(count * 36) signature(64)
+//
+// Each entry is the type and SHA-256 of a signed chunk, in file order, which is
+// what lets `verify` tell which chunks changed. The signature covers the
+// domain tag followed by everything before it in the chunk.
+const VERSION: u8 = 1;
+const DOMAIN: &[u8] = b"pngme signature v1";
+const DIGEST_LEN: usize = 32;
+const ENTRY_LEN: usize = 4 + DIGEST_LEN;
+const SIGNING_KEY_PREFIX: &str = "PNGME-SIGNING-KEY-";
+// Largest table `diff` fills to line up the chunks, about 32 MiB
+const MAX_LCS_CELLS: usize = 1 << 22;
+
+// The type and digest of a signed chunk
+type Entry = ([u8; 4], [u8; DIGEST_LEN]);
+
+/// What happened to a chunk since the file was signed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// A chunk of this type is still there but its contents differ.
+    Altered { index: usize, chunk_type: String },
+    /// A chunk that was not part of the signed file.
+    Added { index: usize, chunk_type: String },
+    /// A signed chunk that is no longer in the file.
+    Removed { index: usize, chunk_type: String },
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::Altered { index, chunk_type } => {
+                write!(f, "altered: {} chunk #{}", chunk_type, index)
+            }
+            Change::Added { index, chunk_type } => {
+                write!(f, "added:   {} chunk #{}", chunk_type, index)
+            }
+            Change::Removed { index, chunk_type } => {
+                write!(f, "removed: {} chunk #{} (as signed)", chunk_type, index)
+            }
+        }
+    }
+}
+
+/// Result of checking a signed file.
+#[derive(Debug)]
+pub struct Verification {
+    /// Key the file was signed with.
+    pub signer: VerifyingKey,
+    /// Differences between the signed and the current chunks, empty if the
+    /// file is untouched.
+    pub changes: Vec<Change>,
+}
+
+/// Signs every chunk of `png` except the signature chunk, replacing any
+/// previous signature.
+pub fn sign(png: &mut Png, key: &SigningKey) -> crate::Result<()> {
+    while png.remove_first_chunk(SIGNATURE_CHUNK).is_ok() {}
+
+    let signed = PngRef::from(&*png);
+    let entries = entries(&signed, None);
+    let mut data: Vec<u8> = vec![VERSION];
+    data.extend_from_slice(key.verifying_key().as_bytes());
+    data.extend_from_slice(&stream_digest(&signed, None));
+    data.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+    for (chunk_type, digest) in &entries {
+        data.extend_from_slice(chunk_type);
+        data.extend_from_slice(digest);
+    }
+
+    let signature = key.sign(&signed_message(&data));
+    data.extend_from_slice(&signature.to_bytes());
+
+    png.insert_chunk(Chunk::new(ChunkType::from_str(SIGNATURE_CHUNK)?, data))?;
+    Ok(())
+}
+
+/// Checks the signature chunk of `png` and compares the signed chunks with
+/// the current ones.
+///
+/// Fails with [`Error::InvalidSignature`] if the signature chunk itself was
+/// forged or damaged, in which case nothing it says can be trusted.
+pub fn verify<'a>(png: impl Into<PngRef<'a>>) -> crate::Result<Verification> {
+    let png = png.into();
+    let position = png
+        .chunks()
+        .iter()
+        .position(|c| c.chunk_type().as_str() == SIGNATURE_CHUNK)
+        .ok_or_else(|| Error::ChunkNotFound(SIGNATURE_CHUNK.to_string()))?;
+    let data = png.chunks()[position].data();
+
+    if data.len() < 1 + 32 + DIGEST_LEN + 4 + 64 || data[0] != VERSION {
+        return Err(Error::InvalidSignature);
+    }
+    let (message, signature) = data.split_at(data.len() - 64);
+    let signer = VerifyingKey::from_bytes(message[1..33].try_into().unwrap())
+        .map_err(|_| Error::InvalidSignature)?;
+    let signature = Signature::from_bytes(signature.try_into().unwrap());
+    signer
+        .verify(&signed_message(message), &signature)
+        .map_err(|_| Error::InvalidSignature)?;
+
+    let stored_digest = &message[33..33 + DIGEST_LEN];
+    let count_start = 33 + DIGEST_LEN;
+    let count = u32::from_be_bytes(message[count_start..count_start + 4].try_into().unwrap());
+    let entries_bytes = &message[count_start + 4..];
+    if entries_bytes.len() != count as usize * ENTRY_LEN {
+        return Err(Error::InvalidSignature);
+    }
+    let signed: Vec<Entry> = entries_bytes
+        .chunks_exact(ENTRY_LEN)
+        .map(|entry| {
+            (
+                entry[..4].try_into().unwrap(),
+                entry[4..].try_into().unwrap(),
+            )
+        })
+        .collect();
+
+    let changes = if stream_digest(&png, Some(position)) == stored_digest {
+        Vec::new()
+    } else {
+        diff(&signed, &entries(&png, Some(position)))
+    };
+
+    Ok(Verification { signer, changes })
+}
+
+/// Generates a new Ed25519 signing key.
+pub fn generate_signing_key() -> SigningKey {
+    SigningKey::generate(&mut OsRng)
+}
+
+/// Formats a verifying key the way it is passed to `verify --public-key`.
+pub fn format_verifying_key(key: &VerifyingKey) -> String {
+    hex::encode(key.as_bytes())
+}
+
+/// Parses a verifying key as printed by [`format_verifying_key`].
+pub fn parse_verifying_key(s: &str) -> crate::Result<VerifyingKey> {
+    hex::decode(s.trim())
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+        .ok_or_else(|| Error::InvalidKey(format!("{:?} is not a public signing key", s)))
+}
+
+/// Formats a signing key as the contents of a key file.
+pub fn format_signing_key(key: &SigningKey) -> String {
+    format!(
+        "# public key: {}\n{}{}\n",
+        format_verifying_key(&key.verifying_key()),
+        SIGNING_KEY_PREFIX,
+        hex::encode(key.to_bytes())
+    )
+}
+
+/// Parses the contents of a key file written by [`format_signing_key`].
+pub fn parse_signing_key(s: &str) -> crate::Result<SigningKey> {
+    s.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .and_then(|line| line.strip_prefix(SIGNING_KEY_PREFIX))
+        .and_then(|hex| hex::decode(hex).ok())
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .map(|bytes| SigningKey::from_bytes(&bytes))
+        .ok_or_else(|| Error::InvalidKey("key file contains no signing key".to_string()))
+}
+
+fn signed_message(data: &[u8]) -> Vec<u8> {
+    DOMAIN.iter().chain(data).copied().collect()
+}
+
+// The chunks covered by the signature, in file order: all but the signature
+// chunk at `signature`. Any other signature chunk is covered like the rest,
+// so it cannot be used to slip data into a signed file.
+fn signed_chunks<'p, 'a>(
+    png: &'p PngRef<'a>,
+    signature: Option<usize>,
+) -> impl Iterator<Item = &'p ChunkRef<'a>> {
+    png.chunks()
+        .iter()
+        .enumerate()
+        .filter(move |&(index, _)| Some(index) != signature)
+        .map(|(_, chunk)| chunk)
+}
+
+// Hashes a chunk as it is laid out in the file, without copying its data
+fn hash_chunk(hasher: &mut Sha256, chunk: &ChunkRef) {
+    hasher.update(chunk.length().to_be_bytes());
+    hasher.update(chunk.chunk_type().bytes());
+    hasher.update(chunk.data());
+    hasher.update(chunk.crc().to_be_bytes());
+}
+
+// SHA-256 of the canonical byte stream: signature header and all signed chunks
+fn stream_digest(png: &PngRef, signature: Option<usize>) -> [u8; DIGEST_LEN] {
+    let mut hasher = Sha256::new();
+    hasher.update(Png::STANDARD_HEADER);
+    for chunk in signed_chunks(png, signature) {
+        hash_chunk(&mut hasher, chunk);
+    }
+    hasher.finalize().into()
+}
+
+fn entries(png: &PngRef, signature: Option<usize>) -> Vec<Entry> {
+    signed_chunks(png, signature)
+        .map(|chunk| {
+            let mut hasher = Sha256::new();
+            hash_chunk(&mut hasher, chunk);
+            (chunk.chunk_type().bytes(), hasher.finalize().into())
+        })
+        .collect()
+}
+
+// Lines up the signed and current chunks with a longest common subsequence.
+// Between two matched chunks, unmatched chunks of the same type are reported
+// as altered and the rest as removed or added.
+fn diff(signed: &[Entry], current: &[Entry]) -> Vec<Change> {
+    // Chunks matching at both ends are set aside, which usually leaves little
+    let prefix = signed
+        .iter()
+        .zip(current)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = signed[prefix..]
+        .iter()
+        .rev()
+        .zip(current[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (n, m) = (
+        signed.len() - prefix - suffix,
+        current.len() - prefix - suffix,
+    );
+    if n.saturating_mul(m) > MAX_LCS_CELLS {
+        return unordered_diff(signed, current);
+    }
+
+    let at = |i: usize, j: usize| signed[prefix + i] == current[prefix + j];
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if at(i, j) {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let mut removed: Vec<usize> = Vec::new();
+    let mut added: Vec<usize> = Vec::new();
+    loop {
+        let matched = i < n && j < m && at(i, j);
+        if matched || (i == n && j == m) {
+            pair_up(signed, current, &mut removed, &mut added, &mut changes);
+            if !matched {
+                break;
+            }
+            i += 1;
+            j += 1;
+        } else if j < m && (i == n || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            added.push(prefix + j);
+            j += 1;
+        } else {
+            removed.push(prefix + i);
+            i += 1;
+        }
+    }
+    changes
+}
+
+// For files too large to line up: chunks are matched by contents wherever
+// they are, and if they all match but moved, the first one out of place is
+// reported as altered
+fn unordered_diff(signed: &[Entry], current: &[Entry]) -> Vec<Change> {
+    let mut positions: HashMap<&Entry, VecDeque<usize>> = HashMap::new();
+    for (i, entry) in signed.iter().enumerate() {
+        positions.entry(entry).or_default().push_back(i);
+    }
+    let mut added: Vec<usize> = Vec::new();
+    for (j, entry) in current.iter().enumerate() {
+        if positions
+            .get_mut(entry)
+            .and_then(VecDeque::pop_front)
+            .is_none()
+        {
+            added.push(j);
+        }
+    }
+    let mut removed: Vec<usize> = positions.into_values().flatten().collect();
+    removed.sort_unstable();
+
+    let mut changes = Vec::new();
+    pair_up(signed, current, &mut removed, &mut added, &mut changes);
+    if changes.is_empty()
+        && let Some(j) = (0..current.len()).find(|&j| signed[j] != current[j])
+    {
+        changes.push(Change::Altered {
+            index: j,
+            chunk_type: String::from_utf8_lossy(&current[j].0).into_owned(),
+        });
+    }
+    changes
+}
+
+fn pair_up(
+    signed: &[Entry],
+    current: &[Entry],
+    removed: &mut Vec<usize>,
+    added: &mut Vec<usize>,
+    changes: &mut Vec<Change>,
+) {
+    let name = |bytes: &[u8; 4]| String::from_utf8_lossy(bytes).into_owned();
+
+    for &j in added.iter() {
+        let chunk_type = current[j].0;
+        if let Some(pos) = removed.iter().position(|&i| signed[i].0 == chunk_type) {
+            removed.remove(pos);
+            changes.push(Change::Altered {
+                index: j,
+                chunk_type: name(&chunk_type),
+            });
+        } else {
+            changes.push(Change::Added {
+                index: j,
+                chunk_type: name(&chunk_type),
+            });
+        }
+    }
+    for &i in removed.iter() {
+        changes.push(Change::Removed {
+            index: i,
+            chunk_type: name(&signed[i].0),
+        });
+    }
+    removed.clear();
+    added.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(chunk_type: &str, data: &str) -> Chunk {
+        Chunk::new(
+            ChunkType::from_str(chunk_type).unwrap(),
+            data.as_bytes().to_vec(),
+        )
+    }
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            chunk("IHDR", "header"),
+            chunk("tEXt", "comment"),
+            chunk("IDAT", "pixels"),
+            chunk("IEND", ""),
+        ])
+    }
+
+    fn signed_png() -> (Png, SigningKey) {
+        let key = generate_signing_key();
+        let mut png = testing_png();
+        sign(&mut png, &key).unwrap();
+        (png, key)
+    }
+
+    fn rebuild(png: &Png, f: impl FnOnce(&mut Vec<Chunk>)) -> Png {
+        let mut chunks: Vec<Chunk> = png
+            .chunks()
+            .iter()
+            .map(|c| Chunk::try_from(c.as_bytes().as_slice()).unwrap())
+            .collect();
+        f(&mut chunks);
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_untouched_file_verifies() {
+        let (png, key) = signed_png();
+
+        let verification = verify(&png).unwrap();
+        assert_eq!(verification.signer, key.verifying_key());
+        assert!(verification.changes.is_empty());
+    }
+
+    #[test]
+    fn test_signature_goes_before_iend() {
+        let (png, _) = signed_png();
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types[types.len() - 2..], [SIGNATURE_CHUNK, "IEND"]);
+    }
+
+    #[test]
+    fn test_resigning_replaces_signature() {
+        let (mut png, key) = signed_png();
+        sign(&mut png, &key).unwrap();
+
+        let signatures = png
+            .chunks()
+            .iter()
+            .filter(|c| c.chunk_type().to_string() == SIGNATURE_CHUNK)
+            .count();
+        assert_eq!(signatures, 1);
+        assert!(verify(&png).unwrap().changes.is_empty());
+    }
+
+    #[test]
+    fn test_reports_altered_added_and_removed() {
+        let (png, _) = signed_png();
+        let png = rebuild(&png, |chunks| {
+            chunks[1] = chunk("tEXt", "edited comment");
+            chunks.remove(2);
+            chunks.insert(2, chunk("ruSt", "hidden"));
+        });
+
+        let changes = verify(&png).unwrap().changes;
+        assert_eq!(
+            changes,
+            vec![
+                Change::Altered {
+                    index: 1,
+                    chunk_type: "tEXt".to_string()
+                },
+                Change::Added {
+                    index: 2,
+                    chunk_type: "ruSt".to_string()
+                },
+                Change::Removed {
+                    index: 2,
+                    chunk_type: "IDAT".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extra_signature_chunk_is_added() {
+        let (mut png, _) = signed_png();
+        png.insert_chunk(chunk(SIGNATURE_CHUNK, "hidden payload"))
+            .unwrap();
+
+        assert_eq!(
+            verify(&png).unwrap().changes,
+            vec![Change::Added {
+                index: 3,
+                chunk_type: SIGNATURE_CHUNK.to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_large_diff_is_bounded() {
+        let entry = |i: u32| {
+            let mut digest = [0; DIGEST_LEN];
+            digest[..4].copy_from_slice(&i.to_be_bytes());
+            (*b"tEXt", digest)
+        };
+        let signed: Vec<Entry> = (0..5000).map(entry).collect();
+
+        // One chunk inserted, found without lining up 25 million cells
+        let mut current = signed.clone();
+        current.insert(2500, (*b"ruSt", [0; DIGEST_LEN]));
+        assert_eq!(
+            diff(&signed, &current),
+            vec![Change::Added {
+                index: 2500,
+                chunk_type: "ruSt".to_string()
+            }]
+        );
+
+        // Chunks moving around are still reported
+        let mut current = signed.clone();
+        current[..2000].reverse();
+        current[3000..].reverse();
+        assert_eq!(
+            diff(&signed, &current),
+            vec![Change::Altered {
+                index: 0,
+                chunk_type: "tEXt".to_string()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_forged_signature_is_rejected() {
+        let (png, _) = signed_png();
+        let png = rebuild(&png, |chunks| {
+            let pos = chunks
+                .iter()
+                .position(|c| c.chunk_type().to_string() == SIGNATURE_CHUNK)
+                .unwrap();
+            let mut data = chunks[pos].data().to_vec();
+            data[40] ^= 1;
+            chunks[pos] = Chunk::new(ChunkType::from_str(SIGNATURE_CHUNK).unwrap(), data);
+        });
+
+        assert!(matches!(verify(&png), Err(Error::InvalidSignature)));
+    }
+
+    #[test]
+    fn test_signing_key_round_trip() {
+        let key = generate_signing_key();
+
+        let parsed = parse_signing_key(&format_signing_key(&key)).unwrap();
+        assert_eq!(parsed.to_bytes(), key.to_bytes());
+
+        let public = format_verifying_key(&key.verifying_key());
+        assert_eq!(parse_verifying_key(&public).unwrap(), key.verifying_key());
+    }
+}
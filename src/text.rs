@@ -0,0 +1,438 @@
+use std::{
+    fmt::Display,
+    io::{Read, Write},
+    str::FromStr,
+};
+
+use flate2::{Compression, read::ZlibDecoder, write::ZlibEncoder};
+
+use crate::{
+    Error,
+    chunk::{Chunk, ChunkRef},
+    chunk_type::ChunkType,
+    png::Png,
+};
+
+pub const TEXT_CHUNK: &str = "tEXt";
+pub const COMPRESSED_TEXT_CHUNK: &str = "zTXt";
+pub const INTERNATIONAL_TEXT_CHUNK: &str = "iTXt";
+
+// The only compression method defined for zTXt and iTXt: zlib deflate
+const COMPRESSION_DEFLATE: u8 = 0;
+
+// Compressed text is never inflated past this, so a few bytes of deflate
+// bomb cannot claim all the memory
+const MAX_TEXT_LEN: u64 = 8 * 1024 * 1024;
+
+/// Uncompressed Latin-1 text, stored in a tEXt chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Text {
+    pub keyword: String,
+    pub text: String,
+}
+
+/// Deflate-compressed Latin-1 text, stored in a zTXt chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressedText {
+    pub keyword: String,
+    pub text: String,
+}
+
+/// UTF-8 text with an optional language, stored in an iTXt chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InternationalText {
+    pub keyword: String,
+    pub compressed: bool,
+    /// RFC 3066 language tag, empty if unknown.
+    pub language_tag: String,
+    /// The keyword translated into the language of the text.
+    pub translated_keyword: String,
+    pub text: String,
+}
+
+/// Any of the three textual chunk kinds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextualChunk {
+    Text(Text),
+    Compressed(CompressedText),
+    International(InternationalText),
+}
+
+impl TextualChunk {
+    /// Returns true if `chunk_type` is tEXt, zTXt or iTXt.
+    pub fn is_textual(chunk_type: &ChunkType) -> bool {
+        matches!(
+            chunk_type.as_str(),
+            TEXT_CHUNK | COMPRESSED_TEXT_CHUNK | INTERNATIONAL_TEXT_CHUNK
+        )
+    }
+
+    pub fn keyword(&self) -> &str {
+        match self {
+            TextualChunk::Text(t) => &t.keyword,
+            TextualChunk::Compressed(t) => &t.keyword,
+            TextualChunk::International(t) => &t.keyword,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        match self {
+            TextualChunk::Text(t) => &t.text,
+            TextualChunk::Compressed(t) => &t.text,
+            TextualChunk::International(t) => &t.text,
+        }
+    }
+
+    pub fn chunk_type(&self) -> &'static str {
+        match self {
+            TextualChunk::Text(_) => TEXT_CHUNK,
+            TextualChunk::Compressed(_) => COMPRESSED_TEXT_CHUNK,
+            TextualChunk::International(_) => INTERNATIONAL_TEXT_CHUNK,
+        }
+    }
+
+    /// Builds the chunk, validating the keyword and encoding the text.
+    pub fn to_chunk(&self) -> crate::Result<Chunk> {
+        validate_keyword(self.keyword())?;
+
+        let mut data = latin1_bytes(self.keyword())?;
+        data.push(0);
+        match self {
+            TextualChunk::Text(t) => {
+                data.extend(latin1_bytes(&t.text)?);
+            }
+            TextualChunk::Compressed(t) => {
+                data.push(COMPRESSION_DEFLATE);
+                data.extend(deflate(&latin1_bytes(&t.text)?)?);
+            }
+            TextualChunk::International(t) => {
+                if t.language_tag.contains('\0') || t.translated_keyword.contains('\0') {
+                    return Err(Error::InvalidText(
+                        "language tag and translated keyword cannot contain null bytes".to_string(),
+                    ));
+                }
+                data.push(t.compressed as u8);
+                data.push(COMPRESSION_DEFLATE);
+                data.extend_from_slice(t.language_tag.as_bytes());
+                data.push(0);
+                data.extend_from_slice(t.translated_keyword.as_bytes());
+                data.push(0);
+                if t.compressed {
+                    data.extend(deflate(t.text.as_bytes())?);
+                } else {
+                    data.extend_from_slice(t.text.as_bytes());
+                }
+            }
+        }
+
+        Ok(Chunk::new(ChunkType::from_str(self.chunk_type())?, data))
+    }
+}
+
+impl TryFrom<&Chunk> for TextualChunk {
+    type Error = crate::Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        TextualChunk::try_from(ChunkRef::from(chunk))
+    }
+}
+
+impl TryFrom<ChunkRef<'_>> for TextualChunk {
+    type Error = crate::Error;
+
+    fn try_from(chunk: ChunkRef<'_>) -> Result<Self, Self::Error> {
+        let data = chunk.data();
+        let (keyword, rest) = split_at_null(data)
+            .ok_or_else(|| Error::InvalidText("keyword is not null terminated".to_string()))?;
+        let keyword = latin1_string(keyword);
+        validate_keyword(&keyword)?;
+
+        match chunk.chunk_type().to_string().as_str() {
+            TEXT_CHUNK => Ok(TextualChunk::Text(Text {
+                keyword,
+                text: latin1_string(rest),
+            })),
+            COMPRESSED_TEXT_CHUNK => {
+                let (&method, compressed) = rest
+                    .split_first()
+                    .ok_or_else(|| Error::InvalidText("missing compression method".to_string()))?;
+                check_compression_method(method)?;
+                Ok(TextualChunk::Compressed(CompressedText {
+                    keyword,
+                    text: latin1_string(&inflate(compressed)?),
+                }))
+            }
+            INTERNATIONAL_TEXT_CHUNK => {
+                if rest.len() < 2 {
+                    return Err(Error::InvalidText("missing compression fields".to_string()));
+                }
+                let compressed = match rest[0] {
+                    0 => false,
+                    1 => true,
+                    other => {
+                        return Err(Error::InvalidText(format!(
+                            "invalid compression flag {}",
+                            other
+                        )));
+                    }
+                };
+                if compressed {
+                    check_compression_method(rest[1])?;
+                }
+                let (language_tag, rest) = split_at_null(&rest[2..]).ok_or_else(|| {
+                    Error::InvalidText("language tag is not null terminated".to_string())
+                })?;
+                let (translated_keyword, text) = split_at_null(rest).ok_or_else(|| {
+                    Error::InvalidText("translated keyword is not null terminated".to_string())
+                })?;
+                let text = if compressed {
+                    inflate(text)?
+                } else {
+                    text.to_vec()
+                };
+                Ok(TextualChunk::International(InternationalText {
+                    keyword,
+                    compressed,
+                    language_tag: String::from_utf8(language_tag.to_vec())?,
+                    translated_keyword: String::from_utf8(translated_keyword.to_vec())?,
+                    text: String::from_utf8(text)?,
+                }))
+            }
+            other => Err(Error::InvalidText(format!(
+                "{} is not a textual chunk",
+                other
+            ))),
+        }
+    }
+}
+
+impl Display for TextualChunk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] {}", self.chunk_type(), self.keyword())?;
+        if let TextualChunk::International(t) = self
+            && !t.language_tag.is_empty()
+        {
+            write!(f, " ({}", t.language_tag)?;
+            if !t.translated_keyword.is_empty() {
+                write!(f, ": {}", t.translated_keyword)?;
+            }
+            write!(f, ")")?;
+        }
+        write!(f, ": {}", self.text())
+    }
+}
+
+/// Stores `text` in `png`, replacing any text with the same keyword. The new
+/// chunk goes where the ordering rules allow, before IEND.
+pub fn set_text(png: &mut Png, text: &TextualChunk) -> crate::Result<()> {
+    let chunk = text.to_chunk()?;
+    remove_text(png, text.keyword());
+    png.insert_chunk(chunk)?;
+    Ok(())
+}
+
+/// Removes the textual chunks with the given keyword, returning how many were
+/// removed. Chunks that fail to parse are kept untouched.
+pub fn remove_text(png: &mut Png, keyword: &str) -> usize {
+    let before = png.chunks().len();
+    png.retain_chunks(|c| {
+        !(TextualChunk::is_textual(c.chunk_type())
+            && TextualChunk::try_from(c).is_ok_and(|t| t.keyword() == keyword))
+    });
+    before - png.chunks().len()
+}
+
+/// Checks a keyword against the PNG rules: 1 to 79 printable Latin-1
+/// characters, without leading, trailing or consecutive spaces.
+pub fn validate_keyword(keyword: &str) -> crate::Result<()> {
+    let invalid = |reason: &str| {
+        Err(Error::InvalidText(format!(
+            "keyword {:?} {}",
+            keyword, reason
+        )))
+    };
+
+    let length = keyword.chars().count();
+    if length == 0 || length > 79 {
+        return invalid("must be 1 to 79 characters long");
+    }
+    if !keyword
+        .chars()
+        .all(|c| matches!(c as u32, 32..=126 | 161..=255))
+    {
+        return invalid("must only contain printable Latin-1 characters");
+    }
+    if keyword.starts_with(' ') || keyword.ends_with(' ') || keyword.contains("  ") {
+        return invalid("cannot have leading, trailing or consecutive spaces");
+    }
+    Ok(())
+}
+
+/// Returns true if `s` can be stored in a tEXt or zTXt chunk.
+pub fn is_latin1(s: &str) -> bool {
+    s.chars().all(|c| (c as u32) <= 0xFF && c != '\0')
+}
+
+fn latin1_bytes(s: &str) -> crate::Result<Vec<u8>> {
+    if !is_latin1(s) {
+        return Err(Error::InvalidText(format!(
+            "{:?} cannot be encoded as Latin-1",
+            s
+        )));
+    }
+    Ok(s.chars().map(|c| c as u8).collect())
+}
+
+fn latin1_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+fn split_at_null(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let pos = bytes.iter().position(|&b| b == 0)?;
+    Some((&bytes[..pos], &bytes[pos + 1..]))
+}
+
+fn check_compression_method(method: u8) -> crate::Result<()> {
+    if method != COMPRESSION_DEFLATE {
+        return Err(Error::InvalidText(format!(
+            "unknown compression method {}",
+            method
+        )));
+    }
+    Ok(())
+}
+
+fn deflate(bytes: &[u8]) -> crate::Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(bytes)?;
+    Ok(encoder.finish()?)
+}
+
+fn inflate(bytes: &[u8]) -> crate::Result<Vec<u8>> {
+    let mut text = Vec::new();
+    ZlibDecoder::new(bytes)
+        .take(MAX_TEXT_LEN + 1)
+        .read_to_end(&mut text)
+        .map_err(|err| Error::InvalidText(format!("corrupt compressed text: {}", err)))?;
+    if text.len() as u64 > MAX_TEXT_LEN {
+        return Err(Error::InvalidText(format!(
+            "compressed text inflates past {} bytes",
+            MAX_TEXT_LEN
+        )));
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(text: TextualChunk) {
+        let chunk = text.to_chunk().unwrap();
+        assert_eq!(chunk.chunk_type().to_string(), text.chunk_type());
+        assert_eq!(TextualChunk::try_from(&chunk).unwrap(), text);
+    }
+
+    #[test]
+    fn test_text_round_trip() {
+        round_trip(TextualChunk::Text(Text {
+            keyword: "Author".to_string(),
+            text: "Jos\u{e9} M\u{fc}ller".to_string(),
+        }));
+    }
+
+    #[test]
+    fn test_text_is_latin1_on_disk() {
+        let chunk = TextualChunk::Text(Text {
+            keyword: "Title".to_string(),
+            text: "caf\u{e9}".to_string(),
+        })
+        .to_chunk()
+        .unwrap();
+
+        assert_eq!(chunk.data(), b"Title\0caf\xe9");
+    }
+
+    #[test]
+    fn test_compressed_text_round_trip() {
+        round_trip(TextualChunk::Compressed(CompressedText {
+            keyword: "Comment".to_string(),
+            text: "a".repeat(1000),
+        }));
+    }
+
+    #[test]
+    fn test_international_text_round_trip() {
+        for compressed in [false, true] {
+            round_trip(TextualChunk::International(InternationalText {
+                keyword: "Title".to_string(),
+                compressed,
+                language_tag: "ja".to_string(),
+                translated_keyword: "\u{30bf}\u{30a4}\u{30c8}\u{30eb}".to_string(),
+                text: "\u{3053}\u{3093}\u{306b}\u{3061}\u{306f}".to_string(),
+            }));
+        }
+    }
+
+    #[test]
+    fn test_non_latin1_text_is_rejected() {
+        let text = TextualChunk::Text(Text {
+            keyword: "Title".to_string(),
+            text: "\u{3053}\u{3093}".to_string(),
+        });
+        assert!(text.to_chunk().is_err());
+    }
+
+    #[test]
+    fn test_validate_keyword() {
+        assert!(validate_keyword("Title").is_ok());
+        assert!(validate_keyword("Creation Time").is_ok());
+        assert!(validate_keyword("").is_err());
+        assert!(validate_keyword(&"k".repeat(80)).is_err());
+        assert!(validate_keyword(" Title").is_err());
+        assert!(validate_keyword("Title ").is_err());
+        assert!(validate_keyword("Creation  Time").is_err());
+        assert!(validate_keyword("Tab\tbed").is_err());
+    }
+
+    #[test]
+    fn test_set_text_goes_before_iend() {
+        let chunk =
+            |chunk_type: &str| Chunk::new(ChunkType::from_str(chunk_type).unwrap(), vec![1]);
+        let mut png = Png::from_chunks(vec![chunk("IHDR"), chunk("IDAT"), chunk("IEND")]);
+        let text = |value: &str| {
+            TextualChunk::Text(Text {
+                keyword: "Comment".to_string(),
+                text: value.to_string(),
+            })
+        };
+
+        set_text(&mut png, &text("first")).unwrap();
+        set_text(&mut png, &text("second")).unwrap();
+
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IHDR", "IDAT", "tEXt", "IEND"]);
+        assert_eq!(
+            TextualChunk::try_from(&png.chunks()[2]).unwrap(),
+            text("second")
+        );
+        assert_eq!(remove_text(&mut png, "Comment"), 1);
+    }
+
+    #[test]
+    fn test_compressed_text_is_limited() {
+        let bomb = vec![b'a'; MAX_TEXT_LEN as usize + 1];
+        let mut data = b"Comment\0\0".to_vec();
+        data.extend(deflate(&bomb).unwrap());
+        let chunk = Chunk::new(ChunkType::from_str(COMPRESSED_TEXT_CHUNK).unwrap(), data);
+
+        assert!(matches!(
+            TextualChunk::try_from(&chunk),
+            Err(Error::InvalidText(_))
+        ));
+    }
+}
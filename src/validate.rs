@@ -0,0 +1,512 @@
+use std::{collections::HashMap, fmt::Display};
+
+use serde::Serialize;
+
+use crate::{
+    chunk::ChunkRef,
+    ihdr::{ColorType, Ihdr},
+    png::{Placement, Png, PngRef, is_unique, placement},
+    text::TextualChunk,
+};
+
+const KNOWN_CRITICAL: [&str; 4] = ["IHDR", "PLTE", "IDAT", "IEND"];
+
+/// How bad a spec violation is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Decoders are allowed to accept the file, but it is not quite right.
+    Warning,
+    /// The file breaks a rule strict decoders rely on.
+    Error,
+}
+
+/// A single spec violation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Issue {
+    pub severity: Severity,
+    /// Index of the offending chunk, if the issue is about one chunk.
+    pub chunk_index: Option<usize>,
+    pub chunk_type: Option<String>,
+    pub message: String,
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}: ", severity)?;
+        if let (Some(index), Some(chunk_type)) = (self.chunk_index, &self.chunk_type) {
+            write!(f, "{} chunk #{}: ", chunk_type, index)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Every spec violation found in a file, in the order they were found.
+#[derive(Debug, Clone, Default)]
+pub struct ValidationReport {
+    pub issues: Vec<Issue>,
+}
+
+impl ValidationReport {
+    pub fn errors(&self) -> usize {
+        self.count(Severity::Error)
+    }
+
+    pub fn warnings(&self) -> usize {
+        self.count(Severity::Warning)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.errors() == 0
+    }
+
+    /// Machine-readable form of the report.
+    pub fn to_json(&self) -> String {
+        let json = serde_json::json!({
+            "valid": self.is_valid(),
+            "errors": self.errors(),
+            "warnings": self.warnings(),
+            "issues": self.issues,
+        });
+        serde_json::to_string_pretty(&json).expect("report is always serializable")
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.issues
+            .iter()
+            .filter(|issue| issue.severity == severity)
+            .count()
+    }
+
+    fn file(&mut self, severity: Severity, message: String) {
+        self.issues.push(Issue {
+            severity,
+            chunk_index: None,
+            chunk_type: None,
+            message,
+        });
+    }
+
+    fn chunk(&mut self, severity: Severity, index: usize, chunk: &ChunkRef, message: String) {
+        self.issues.push(Issue {
+            severity,
+            chunk_index: Some(index),
+            chunk_type: Some(chunk.chunk_type().to_string()),
+            message,
+        });
+    }
+}
+
+impl Png {
+    /// Checks the file against the structural rules of the PNG specification.
+    pub fn validate(&self) -> ValidationReport {
+        PngRef::from(self).validate()
+    }
+}
+
+impl PngRef<'_> {
+    /// Checks the file, see [`Png::validate`].
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        let chunks = self.chunks();
+        let types: Vec<String> = chunks.iter().map(|c| c.chunk_type().to_string()).collect();
+        let first = |name: &str| types.iter().position(|t| t == name);
+
+        // Damage skipped or kept by a lenient parse
+        for diagnostic in self.diagnostics() {
+            match diagnostic.chunk() {
+                Some(index) => report.chunk(
+                    Severity::Error,
+                    index,
+                    &chunks[index],
+                    diagnostic.to_string(),
+                ),
+                None => report.file(Severity::Error, diagnostic.to_string()),
+            }
+        }
+
+        // IHDR and IEND
+        let ihdr = match first("IHDR") {
+            None => {
+                report.file(Severity::Error, "missing IHDR chunk".to_string());
+                None
+            }
+            Some(index) => {
+                if index != 0 {
+                    report.chunk(
+                        Severity::Error,
+                        index,
+                        &chunks[index],
+                        "IHDR must be the first chunk".to_string(),
+                    );
+                }
+                match Ihdr::try_from(chunks[index]) {
+                    Ok(ihdr) => Some(ihdr),
+                    Err(err) => {
+                        report.chunk(Severity::Error, index, &chunks[index], err.to_string());
+                        None
+                    }
+                }
+            }
+        };
+        match first("IEND") {
+            None => report.file(Severity::Error, "missing IEND chunk".to_string()),
+            Some(index) if index + 1 < chunks.len() => report.file(
+                Severity::Error,
+                format!("{} chunk(s) after IEND", chunks.len() - index - 1),
+            ),
+            _ => {}
+        }
+
+        // Image data
+        let idat: Vec<usize> = (0..types.len()).filter(|&i| types[i] == "IDAT").collect();
+        match (idat.first(), idat.last()) {
+            (Some(&start), Some(&end)) => {
+                if end - start + 1 != idat.len() {
+                    report.file(
+                        Severity::Error,
+                        "IDAT chunks are not contiguous".to_string(),
+                    );
+                }
+            }
+            _ => report.file(Severity::Error, "missing IDAT chunk".to_string()),
+        }
+
+        // Per chunk rules
+        let mut seen: HashMap<&str, usize> = HashMap::new();
+        for (index, chunk) in chunks.iter().enumerate() {
+            let chunk_type = chunk.chunk_type();
+            let name = types[index].as_str();
+
+            if !chunk_type.is_reserved_bit_valid() {
+                report.chunk(
+                    Severity::Error,
+                    index,
+                    chunk,
+                    "reserved bit is set (third letter must be uppercase)".to_string(),
+                );
+            }
+            if chunk_type.is_critical() && !KNOWN_CRITICAL.contains(&name) {
+                report.chunk(
+                    Severity::Error,
+                    index,
+                    chunk,
+                    "unknown critical chunk, decoders must reject the file".to_string(),
+                );
+            }
+
+            let count = seen.entry(name).or_insert(0);
+            *count += 1;
+            if *count == 2 && is_unique(name) {
+                report.chunk(
+                    Severity::Error,
+                    index,
+                    chunk,
+                    format!("{} must not appear more than once", name),
+                );
+            }
+
+            check_order(&mut report, index, chunk, &types);
+            check_length(&mut report, index, chunk, ihdr.as_ref());
+
+            if TextualChunk::is_textual(chunk_type)
+                && let Err(err) = TextualChunk::try_from(*chunk)
+            {
+                report.chunk(Severity::Warning, index, chunk, err.to_string());
+            }
+        }
+
+        // Palette
+        if let Some(ihdr) = ihdr {
+            check_palette(&mut report, self, &ihdr);
+        }
+        if first("sRGB").is_some() && first("iCCP").is_some() {
+            report.file(
+                Severity::Warning,
+                "sRGB and iCCP should not both be present".to_string(),
+            );
+        }
+
+        report
+    }
+}
+
+fn check_order(report: &mut ValidationReport, index: usize, chunk: &ChunkRef, types: &[String]) {
+    let first = |name: &str| types.iter().position(|t| t == name);
+    let before = |name: &str| first(name).is_some_and(|pos| pos < index);
+    let name = types[index].as_str();
+
+    match placement(name) {
+        Placement::BeforePlte if before("PLTE") || before("IDAT") => report.chunk(
+            Severity::Error,
+            index,
+            chunk,
+            "must come before PLTE and IDAT".to_string(),
+        ),
+        Placement::Plte if before("IDAT") => report.chunk(
+            Severity::Error,
+            index,
+            chunk,
+            "must come before IDAT".to_string(),
+        ),
+        Placement::BeforeIdat if before("IDAT") => report.chunk(
+            Severity::Error,
+            index,
+            chunk,
+            "must come before IDAT".to_string(),
+        ),
+        Placement::BeforeIdat
+            if matches!(name, "tRNS" | "bKGD" | "hIST")
+                && first("PLTE").is_some_and(|pos| pos > index) =>
+        {
+            report.chunk(
+                Severity::Error,
+                index,
+                chunk,
+                "must come after PLTE".to_string(),
+            )
+        }
+        _ => {}
+    }
+}
+
+fn check_length(
+    report: &mut ValidationReport,
+    index: usize,
+    chunk: &ChunkRef,
+    ihdr: Option<&Ihdr>,
+) {
+    let color_type = ihdr.map(|ihdr| ihdr.color_type);
+    let expected: &[usize] = match (chunk.chunk_type().to_string().as_str(), color_type) {
+        ("IEND", _) => &[0],
+        ("gAMA", _) => &[4],
+        ("cHRM", _) => &[32],
+        ("sRGB", _) => &[1],
+        ("pHYs", _) => &[9],
+        ("tIME", _) => &[7],
+        ("cICP", _) => &[4],
+        ("cLLI", _) => &[8],
+        ("mDCV", _) => &[24],
+        ("sBIT", Some(ColorType::Grayscale)) => &[1],
+        ("sBIT", Some(ColorType::GrayscaleAlpha)) => &[2],
+        ("sBIT", Some(ColorType::Rgb | ColorType::Indexed)) => &[3],
+        ("sBIT", Some(ColorType::Rgba)) => &[4],
+        ("bKGD", Some(ColorType::Indexed)) => &[1],
+        ("bKGD", Some(ColorType::Grayscale | ColorType::GrayscaleAlpha)) => &[2],
+        ("bKGD", Some(ColorType::Rgb | ColorType::Rgba)) => &[6],
+        ("tRNS", Some(ColorType::Grayscale)) => &[2],
+        ("tRNS", Some(ColorType::Rgb)) => &[6],
+        _ => return,
+    };
+
+    let length = chunk.data().len();
+    if !expected.contains(&length) {
+        report.chunk(
+            Severity::Error,
+            index,
+            chunk,
+            format!("must be {} bytes long, found {}", expected[0], length),
+        );
+    }
+}
+
+fn check_palette(report: &mut ValidationReport, png: &PngRef, ihdr: &Ihdr) {
+    let chunks = png.chunks();
+    let find = |name: &str| {
+        chunks
+            .iter()
+            .position(|c| c.chunk_type().to_string() == name)
+    };
+
+    match (find("PLTE"), ihdr.color_type) {
+        (None, ColorType::Indexed) => report.file(
+            Severity::Error,
+            "indexed color images require a PLTE chunk".to_string(),
+        ),
+        (Some(index), ColorType::Grayscale | ColorType::GrayscaleAlpha) => report.chunk(
+            Severity::Error,
+            index,
+            &chunks[index],
+            "PLTE is not allowed in grayscale images".to_string(),
+        ),
+        (Some(index), color_type) => {
+            let length = chunks[index].data().len();
+            let entries = length / 3;
+            let max_entries = if color_type == ColorType::Indexed {
+                1 << ihdr.bit_depth.min(8)
+            } else {
+                256
+            };
+            if !length.is_multiple_of(3) || entries == 0 || entries > max_entries {
+                report.chunk(
+                    Severity::Error,
+                    index,
+                    &chunks[index],
+                    format!(
+                        "palette must hold 1 to {} RGB entries, found {} bytes",
+                        max_entries, length
+                    ),
+                );
+            }
+        }
+        _ => {}
+    }
+
+    if let Some(index) = find("tRNS")
+        && matches!(ihdr.color_type, ColorType::GrayscaleAlpha | ColorType::Rgba)
+    {
+        report.chunk(
+            Severity::Error,
+            index,
+            &chunks[index],
+            "tRNS is not allowed in images with an alpha channel".to_string(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::{chunk::Chunk, chunk_type::ChunkType};
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    fn ihdr(color_type: ColorType) -> Chunk {
+        let ihdr = Ihdr {
+            width: 1,
+            height: 1,
+            bit_depth: 8,
+            color_type,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 0,
+        };
+        chunk("IHDR", &ihdr.as_bytes())
+    }
+
+    fn messages(report: &ValidationReport) -> Vec<String> {
+        report.issues.iter().map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn test_valid_file() {
+        let png = Png::from_chunks(vec![
+            ihdr(ColorType::Rgb),
+            chunk("gAMA", &[0, 0, 177, 143]),
+            chunk("IDAT", b"pixels"),
+            chunk("IDAT", b"more pixels"),
+            chunk("IEND", b""),
+        ]);
+
+        let report = png.validate();
+        assert!(report.is_valid(), "{:?}", messages(&report));
+        assert_eq!(report.warnings(), 0);
+    }
+
+    #[test]
+    fn test_missing_ihdr_and_iend() {
+        let png = Png::from_chunks(vec![chunk("IDAT", b"pixels")]);
+
+        let messages = messages(&png.validate());
+        assert!(messages.contains(&"error: missing IHDR chunk".to_string()));
+        assert!(messages.contains(&"error: missing IEND chunk".to_string()));
+    }
+
+    #[test]
+    fn test_structure_errors() {
+        let png = Png::from_chunks(vec![
+            ihdr(ColorType::Rgb),
+            chunk("IDAT", b"pixels"),
+            chunk("gAMA", &[0, 0, 177]),
+            chunk("gAMA", &[0, 0, 177, 143]),
+            chunk("IDAT", b"more pixels"),
+            chunk("ABcD", b""),
+            chunk("IEND", b""),
+            chunk("ruSt", b"trailing"),
+        ]);
+
+        let report = png.validate();
+        let messages = messages(&report);
+        for expected in [
+            "error: 1 chunk(s) after IEND",
+            "error: IDAT chunks are not contiguous",
+            "error: gAMA chunk #2: must come before PLTE and IDAT",
+            "error: gAMA chunk #2: must be 4 bytes long, found 3",
+            "error: gAMA chunk #3: gAMA must not appear more than once",
+            "error: ABcD chunk #5: unknown critical chunk, decoders must reject the file",
+            "error: ABcD chunk #5: reserved bit is set (third letter must be uppercase)",
+        ] {
+            assert!(
+                messages.contains(&expected.to_string()),
+                "missing {:?} in {:?}",
+                expected,
+                messages
+            );
+        }
+        assert!(!report.is_valid());
+    }
+
+    #[test]
+    fn test_palette_rules() {
+        let png = Png::from_chunks(vec![
+            ihdr(ColorType::Indexed),
+            chunk("IDAT", b"pixels"),
+            chunk("IEND", b""),
+        ]);
+        assert!(
+            messages(&png.validate())
+                .contains(&"error: indexed color images require a PLTE chunk".to_string())
+        );
+
+        let png = Png::from_chunks(vec![
+            ihdr(ColorType::Grayscale),
+            chunk("PLTE", &[0, 0, 0]),
+            chunk("IDAT", b"pixels"),
+            chunk("IEND", b""),
+        ]);
+        assert!(messages(&png.validate()).contains(
+            &"error: PLTE chunk #1: PLTE is not allowed in grayscale images".to_string()
+        ));
+    }
+
+    #[test]
+    fn test_reports_damage() {
+        use crate::png::tests::PNG_FILE;
+
+        let intact = PngRef::parse_lenient(&PNG_FILE).unwrap().validate();
+
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.extend_from_slice(b"trailing");
+        let report = PngRef::parse_lenient(&bytes).unwrap().validate();
+        assert_eq!(report.errors(), intact.errors() + 1);
+        assert_eq!(
+            messages(&report)[0],
+            format!("error: 8 bytes after IEND at offset {}", PNG_FILE.len())
+        );
+
+        let mut bytes = PNG_FILE.to_vec();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let report = PngRef::parse_lenient(&bytes).unwrap().validate();
+        assert_eq!(report.errors(), intact.errors() + 1);
+        assert_eq!(report.issues[0].chunk_type.as_deref(), Some("IEND"));
+    }
+
+    #[test]
+    fn test_json_report() {
+        let png = Png::from_chunks(vec![chunk("IDAT", b"pixels")]);
+
+        let json: serde_json::Value = serde_json::from_str(&png.validate().to_json()).unwrap();
+        assert_eq!(json["valid"], false);
+        assert_eq!(json["issues"][0]["severity"], "error");
+        assert_eq!(json["issues"][0]["message"], "missing IHDR chunk");
+    }
+}